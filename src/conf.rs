@@ -1,19 +1,57 @@
 mod conf_error;
 mod conf_builder;
 mod args;
+pub mod listen_addr;
+pub mod access;
+pub mod cors;
+pub mod header;
+pub mod htpasswd;
+pub mod location;
+pub mod rate_limit;
+pub mod redirect;
+pub mod rewrite;
+pub mod server_name;
+pub mod upstream;
+pub mod upstream_tls;
+mod unit;
 
 use crate::conf::conf_builder::ConfBuilder;
+use crate::conf::listen_addr::{Listen, ListenAddr};
+use crate::conf::location::{find_location, Location};
+use crate::conf::access::{AccessRule, Cidr};
+use crate::conf::cors::Cors;
+use crate::conf::header::HeaderRule;
+use crate::conf::rate_limit::RateLimit;
+use crate::conf::redirect::RedirectRule;
+use crate::conf::rewrite::RewriteRule;
+use crate::conf::server_name::ServerName;
+use crate::conf::upstream_tls::UpstreamTls;
+use crate::conf::upstream::{BalanceStrategy, HashKey, HealthCheck, ProxyProtocolVersion, UpstreamGroup, UpstreamServer};
+use std::collections::HashMap;
 use std::error::Error;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::string::FromUtf8Error;
+use std::sync::Arc;
 use std::time::Duration;
+use urlencoding::decode;
 
+#[derive(Clone)]
 pub struct Conf {
     pub dir: String,
     pub port: u16,
+    pub listen: Vec<Listen>,
+    pub socket_mode: Option<u32>,
+    /// Connections start with a PROXY protocol header.
+    pub proxy_protocol: bool,
     pub domain: String,
+    pub server_name: ServerName,
+    pub aliases: Vec<ServerName>,
+    pub default_host: bool,
     pub browsing_enabled: bool,
     pub workers: usize,
+    pub max_connections_per_ip: usize,
+    pub reject_overflow: bool,
     pub timeout: Duration,
     pub php_enabled: bool,
     pub php_index: Option<String>,
@@ -22,19 +60,177 @@ pub struct Conf {
     pub https_enabled: bool,
     pub https_pub_cert: String,
     pub https_private_key: String,
+    pub https_redirect_http: bool,
+    pub https_redirect_port: u16,
+    pub https_redirect_status: u32,
+    pub hsts_max_age: Option<u64>,
+    pub hsts_include_subdomains: bool,
+    pub hsts_preload: bool,
     pub logs_enabled: bool,
     pub logs_min_level: String,
     pub logs_dir: Option<PathBuf>,
     pub load_balancing_enabled: bool,
-    pub load_balancing_servers: Vec<SocketAddr>,
+    pub load_balancing_servers: Vec<UpstreamServer>,
+    pub load_balancing_strategy: BalanceStrategy,
+    pub load_balancing_hash_key: HashKey,
+    pub load_balancing_health_check: HealthCheck,
+    /// Further upstreams tried after a failure.
+    pub load_balancing_retries: usize,
+    pub load_balancing_connect_timeout: Duration,
+    /// Time allowed without data from the upstream or the client while a request is passed.
+    pub load_balancing_read_timeout: Duration,
+    pub load_balancing_proxy_protocol: Option<ProxyProtocolVersion>,
+    pub load_balancing_tls: UpstreamTls,
+    pub load_balancing_keepalive: usize,
+    pub load_balancing_keepalive_timeout: Duration,
+    /// Interval for resolving server host names again, `0` keeps the addresses found at start.
+    pub load_balancing_dns_ttl: Duration,
+    pub proxy_x_forwarded: bool,
+    pub proxy_forwarded: bool,
+    pub proxy_via: bool,
+    /// Rewrites upstream addresses in `Location` and `Refresh` to the requested host.
+    pub proxy_redirect_default: bool,
+    pub proxy_redirects: Vec<(String, String)>,
+    pub proxy_cookie_domains: Vec<(String, String)>,
+    pub proxy_cookie_paths: Vec<(String, String)>,
+    /// Shared by locations which do not change load balancer settings.
+    pub upstream_group: Option<Arc<UpstreamGroup>>,
     pub cache_enabled: bool,
     pub cache_dir: Option<PathBuf>,
-    pub cache_patterns: Vec<String>
+    pub cache_patterns: Vec<String>,
+    pub mime_types: HashMap<String, String>,
+    pub mime_overrides: HashMap<String, String>,
+    pub mime_charset: Option<String>,
+    pub mime_nosniff: bool,
+    /// Location prefix and directory replacing it, see `location.alias`
+    pub alias: Option<(String, String)>,
+    pub rewrites: Vec<RewriteRule>,
+    pub try_files: Vec<String>,
+    pub redirects: Vec<RedirectRule>,
+    pub redirect_map: HashMap<String, RedirectRule>,
+    pub redirect_preserve_query: bool,
+    pub headers: Vec<HeaderRule>,
+    pub cors: Cors,
+    pub auth_realm: Option<String>,
+    pub auth_users: HashMap<String, String>,
+    pub access_rules: Vec<AccessRule>,
+    pub real_ip_header: String,
+    pub trusted_proxies: Vec<Cidr>,
+    pub trust_unix_proxy: bool,
+    /// Shared by locations which do not change rate limit settings.
+    pub rate_limit: Option<Arc<RateLimit>>,
+    pub locations: Vec<Location>
 }
 
 impl Conf {
     pub fn new(args: Vec<String>) -> Result<Conf, Box<dyn Error>> {
         ConfBuilder::new(args)
     }
+
+    /// Configuration of the location matching the decoded request path or the host configuration.
+    pub fn location(&self, path: &str) -> &Conf {
+        match find_location(&self.locations, path) {
+            Some(location) => &location.conf,
+            None => self
+        }
+    }
+
+    pub fn is_trusted_proxy(&self, peer: Option<SocketAddr>) -> bool {
+        match peer {
+            Some(peer) => self.trusted_proxies.iter().any(|c| c.contains(peer.ip())),
+            None => self.trust_unix_proxy
+        }
+    }
+
+    /// Redirect status and URL for the request, exact map entries are checked before rules.
+    pub fn redirect(&self, path: &str, query: &str) -> Option<(u32, String)> {
+        let (status, mut location) = match self.redirect_map.get(path) {
+            Some(rule) => (rule.status, rule.target.clone()),
+            None => self.redirects
+                .iter()
+                .find_map(|r| r.location(path).map(|l| (r.status, l)))?
+        };
+        if self.redirect_preserve_query && !query.is_empty() {
+            location.push(if location.contains('?') { '&' } else { '?' });
+            location.push_str(query);
+        }
+        Some((status, location))
+    }
+
+    /// Maps request path to a file in the document root (or alias directory).
+    pub fn file_path(&self, path: &str) -> Result<PathBuf, FromUtf8Error> {
+        // `/static` is replaced in `/static/a` but not in `/staticfoo/a`
+        let alias = self.alias.as_ref().and_then(|(prefix, dir)| {
+            path.strip_prefix(prefix.as_str())
+                .filter(|rest| rest.is_empty() || rest.starts_with('/'))
+                .map(|rest| (dir, rest))
+        });
+        let (dir, path) = alias.unwrap_or((&self.dir, path));
+        let mut local_path = String::from(dir);
+        if !path.contains("/..") {
+            local_path.push_str(path);
+        }
+
+        let local_path = decode(&local_path)?;
+        Ok(PathBuf::from(local_path.as_ref()))
+    }
+
+    /// Domain name followed by the aliases.
+    pub fn server_names(&self) -> impl Iterator<Item = &ServerName> {
+        std::iter::once(&self.server_name).chain(self.aliases.iter())
+    }
+
+    /// Listen addresses with a flag telling whether connections use TLS.
+    pub fn listen_addrs(&self) -> Vec<(ListenAddr, bool)> {
+        let mut addrs: Vec<(ListenAddr, bool)> = if self.listen.is_empty() {
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), self.port);
+            vec![(ListenAddr::Tcp(addr), self.https_enabled)]
+        } else {
+            self.listen
+                .iter()
+                .map(|l| (l.addr.clone(), l.tls.unwrap_or(self.https_enabled)))
+                .collect()
+        };
+        if self.https_enabled && self.https_redirect_http {
+            let redirects: Vec<(ListenAddr, bool)> = addrs
+                .iter()
+                .filter_map(|(addr, tls)| match addr {
+                    ListenAddr::Tcp(a) if *tls => {
+                        let addr = SocketAddr::new(a.ip(), self.https_redirect_port);
+                        Some((ListenAddr::Tcp(addr), false))
+                    }
+                    _ => None
+                })
+                .collect();
+            for redirect in redirects {
+                if !addrs.contains(&redirect) {
+                    addrs.push(redirect);
+                }
+            }
+        }
+        addrs
+    }
+
+    /// Port of the first HTTPS listener, used to build redirect URLs.
+    pub fn https_port(&self) -> Option<u16> {
+        self.listen_addrs()
+            .iter()
+            .find_map(|(addr, tls)| match addr {
+                ListenAddr::Tcp(a) if *tls => Some(a.port()),
+                _ => None
+            })
+    }
+
+    pub fn hsts_header(&self) -> Option<String> {
+        let max_age = self.hsts_max_age?;
+        let mut value = format!("max-age={}", max_age);
+        if self.hsts_include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.hsts_preload {
+            value.push_str("; preload");
+        }
+        Some(value)
+    }
 }
 
@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::net::IpAddr;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Parses `10.0.0.0/8`, `2001:db8::/32` or a single address.
+    pub fn parse(value: &str) -> Result<Cidr, Box<dyn Error>> {
+        let value = value.trim();
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None)
+        };
+        let addr = match addr.parse::<IpAddr>() {
+            Ok(addr) => addr.to_canonical(),
+            Err(_) => Err(format!("Invalid IP address {}", value))?
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix.map(|p| p.parse::<u8>()) {
+            None => max,
+            Some(Ok(prefix)) if prefix <= max => prefix,
+            Some(_) => Err(format!("Invalid CIDR prefix {}", value))?
+        };
+        Ok(Cidr { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AccessRule {
+    pub allow: bool,
+    /// `None` matches all clients.
+    pub cidr: Option<Cidr>,
+}
+
+impl AccessRule {
+    pub fn parse(allow: bool, value: &str) -> Result<AccessRule, Box<dyn Error>> {
+        let cidr = match value.trim() {
+            "all" => None,
+            value => Some(Cidr::parse(value)?)
+        };
+        Ok(AccessRule { allow, cidr })
+    }
+
+    pub fn matches(&self, ip: Option<IpAddr>) -> bool {
+        match (&self.cidr, ip) {
+            (None, _) => true,
+            (Some(cidr), Some(ip)) => cidr.contains(ip),
+            (Some(_), None) => false
+        }
+    }
+}
+
+/// First matching rule decides, clients not matching any rule are allowed.
+pub fn is_allowed(rules: &[AccessRule], ip: Option<IpAddr>) -> bool {
+    rules.iter().find(|r| r.matches(ip)).is_none_or(|r| r.allow)
+}
+
+/// Client address behind trusted proxies. Addresses in the header are checked from the right,
+/// the first one which is not a trusted proxy is the client.
+pub fn real_ip(peer: Option<IpAddr>, peer_trusted: bool, header: Option<&str>, trusted: &[Cidr]) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|c| c.contains(ip));
+    if !peer_trusted && !peer.is_some_and(is_trusted) {
+        return peer;
+    }
+    let mut client = peer;
+    for value in header.unwrap_or_default().rsplit(',') {
+        let ip = match value.trim().parse::<IpAddr>() {
+            Ok(ip) => ip.to_canonical(),
+            Err(_) => break
+        };
+        client = Some(ip);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
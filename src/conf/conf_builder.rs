@@ -1,9 +1,23 @@
 use crate::conf::args::args_parser::{ArgKind, ArgsParser};
 use crate::conf::conf_error::ConfError;
+use crate::conf::listen_addr::{Listen, ListenAddr};
+use crate::conf::location::{Location, LocationMatch};
+use crate::conf::access::{AccessRule, Cidr};
+use crate::conf::htpasswd::load_htpasswd;
+use crate::conf::rate_limit::{RateLimit, RateLimitKey};
+use crate::conf::cors::Cors;
+use crate::conf::header::{HeaderAction, HeaderRule};
+use crate::conf::redirect::{load_redirect_map, RedirectRule};
+use crate::conf::rewrite::RewriteRule;
+use crate::conf::server_name::ServerName;
+use crate::conf::upstream_tls::{TlsClient, UpstreamTls};
+use crate::conf::upstream::{BalanceStrategy, HashKey, HealthCheck, ProxyProtocolVersion, UpstreamGroup, UpstreamServer, DEFAULT_DNS_TTL};
 use crate::conf::Conf;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::net::{IpAddr, SocketAddr};
-use std::path::Path;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::str::FromStr;
 use std::time::Duration;
 use std::{env, fs, u16, u64};
@@ -19,9 +33,17 @@ impl ConfBuilder {
         let mut conf = Conf {
             dir,
             port: 80,
+            listen: Vec::new(),
+            socket_mode: None,
+            proxy_protocol: false,
             domain: "localhost".to_string(),
+            server_name: ServerName::Exact("localhost".to_string()),
+            aliases: Vec::new(),
+            default_host: false,
             browsing_enabled: true,
             workers: 64,
+            max_connections_per_ip: 0,
+            reject_overflow: false,
             timeout: Duration::from_secs(30),
             php_enabled: true,
             php_index: None,
@@ -30,17 +52,69 @@ impl ConfBuilder {
             https_enabled: false,
             https_pub_cert: "".to_string(),
             https_private_key: "".to_string(),
+            https_redirect_http: false,
+            https_redirect_port: 80,
+            https_redirect_status: 301,
+            hsts_max_age: None,
+            hsts_include_subdomains: false,
+            hsts_preload: false,
             logs_enabled: true,
             logs_min_level: "info".to_string(),
             logs_dir: None,
             load_balancing_enabled: false,
             load_balancing_servers: Vec::new(),
+            load_balancing_strategy: BalanceStrategy::RoundRobin,
+            load_balancing_hash_key: HashKey::Ip,
+            load_balancing_health_check: HealthCheck::default(),
+            load_balancing_retries: 1,
+            load_balancing_connect_timeout: Duration::from_secs(5),
+            load_balancing_read_timeout: Duration::from_secs(60),
+            load_balancing_proxy_protocol: None,
+            load_balancing_tls: UpstreamTls::default(),
+            load_balancing_keepalive: 16,
+            load_balancing_keepalive_timeout: Duration::from_secs(60),
+            load_balancing_dns_ttl: DEFAULT_DNS_TTL,
+            proxy_x_forwarded: true,
+            proxy_forwarded: false,
+            proxy_via: false,
+            proxy_redirect_default: true,
+            proxy_redirects: Vec::new(),
+            proxy_cookie_domains: Vec::new(),
+            proxy_cookie_paths: Vec::new(),
+            upstream_group: None,
             cache_enabled: false,
             cache_dir: None,
             cache_patterns: Vec::new(),
+            mime_types: HashMap::new(),
+            mime_overrides: HashMap::new(),
+            mime_charset: None,
+            mime_nosniff: false,
+            alias: None,
+            rewrites: Vec::new(),
+            try_files: Vec::new(),
+            redirects: Vec::new(),
+            redirect_map: HashMap::new(),
+            redirect_preserve_query: true,
+            headers: Vec::new(),
+            cors: Cors::default(),
+            auth_realm: None,
+            auth_users: HashMap::new(),
+            access_rules: Vec::new(),
+            real_ip_header: "X-Forwarded-For".to_string(),
+            trusted_proxies: Vec::new(),
+            trust_unix_proxy: false,
+            rate_limit: None,
+            locations: Vec::new(),
         };
 
         Self::parse_args(&mut conf, args)?;
+        if conf.listen_addrs().iter().any(|(_, tls)| *tls)
+            && (conf.https_pub_cert.is_empty() || conf.https_private_key.is_empty()) {
+            return Err("HTTPS requires https.public_key and https.private_key")?;
+        }
+        for location in conf.locations.iter_mut() {
+            location.conf.port = conf.port;
+        }
 
         Ok(conf)
     }
@@ -50,6 +124,7 @@ impl ConfBuilder {
         parser.add(ArgKind::Value("-f".to_string()));
         parser.add(ArgKind::Value("-p".to_string()));
         parser.add(ArgKind::Value("-d".to_string()));
+        parser.add(ArgKind::Value("-l".to_string()));
 
         let args = parser.parse(&args)?;
 
@@ -68,144 +143,695 @@ impl ConfBuilder {
         }
 
         if let Some(dir) = args.get("-d") {
-            conf.dir  = Self::parse_path(dir)?
+            let dir = Self::parse_path(dir)?;
+            // Locations without their own directory follow the domain
+            for location in conf.locations.iter_mut().filter(|l| l.conf.dir == conf.dir) {
+                location.conf.dir = dir.clone();
+            }
+            conf.dir = dir;
+        }
+
+        if let Some(listen) = args.get("-l") {
+            let mut addrs = Vec::new();
+            for addr in listen.split(',') {
+                addrs.push(Self::parse_listen(addr, "Listen address is not valid")?);
+            }
+            conf.listen = addrs;
         }
 
         Ok(())
     }
 
     fn parse_file(conf: &mut Conf, path: &str) -> Result<(), Box<dyn Error>> {
-        let enabled_values = ["1", "true", "t", "enabled", "y", "yes"];
-        let mut line_no = 1;
         let contents = fs::read_to_string(path)?;
-        for line in contents.lines() {
-            if line.trim().starts_with(";") {
+        let mut location: Option<Location> = None;
+        let mut section_keys: HashSet<String> = HashSet::new();
+        // Line of the last key changing the upstream group, the group is built at the end of the section
+        let mut group_line: Option<usize> = None;
+        for (idx, line) in contents.lines().enumerate() {
+            let line_no = idx + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(";") {
                 continue;
             }
-            let split: Vec<&str> = line.split("=").collect();
-            let key = split.first().unwrap().trim();
-            let value = split.last().unwrap().trim();
-
-            if key == "server.port" {
-                conf.port = Self::parse_u16(
-                    value,
-                    format!("Port is not valid integer. Line no. {}", line_no).as_str(),
-                )?;
-            }
-            if key == "server.dir" {
-                conf.dir = value.to_string();
+            if line.starts_with('[') && line.ends_with(']') {
+                Self::end_section(conf, location.take(), group_line.take())?;
+                location = Some(Self::parse_location(conf, &line[1..line.len() - 1], line_no)?);
+                section_keys.clear();
+                continue;
             }
-            if key == "server.workers" {
-                conf.workers = Self::parse_usize(
-                    value,
-                    format!("Workers is not valid integer. Line no. {}", line_no).as_str(),
-                )?;
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue
+            };
+            if Self::is_upstream_group_key(key) {
+                group_line = Some(line_no);
             }
-            if key == "server.timeout" {
-                let timeout = Self::parse_u16(
-                    value,
-                    format!("Timeout is not valid integer. Line no. {}", line_no).as_str(),
-                )?;
-                let timeout = u64::from(timeout);
-                conf.timeout = Duration::from_secs(timeout);
+
+            match location.as_mut() {
+                Some(location) => {
+                    if Self::is_server_key(key) {
+                        return Err(format!("{} is not allowed in location. Line no. {}", key, line_no))?;
+                    }
+                    if key == "location.alias" {
+                        location.set_alias(value, line_no)?;
+                        continue;
+                    }
+                    if section_keys.insert(Self::list_name(key).to_string()) {
+                        Self::reset_list(&mut location.conf, key);
+                    }
+                    Self::parse_line(&mut location.conf, key, value, line_no)?;
+                }
+                None => Self::parse_line(conf, key, value, line_no)?
             }
-            if key == "server.domain" {
-                conf.domain = value.to_string();
+        }
+        Self::end_section(conf, location, group_line)?;
+
+        Ok(())
+    }
+
+    /// Completes the domain settings or a location once its section ends.
+    fn end_section(conf: &mut Conf, location: Option<Location>, group_line: Option<usize>) -> Result<(), Box<dyn Error>> {
+        match location {
+            Some(mut location) => {
+                if let Some(line_no) = group_line {
+                    Self::build_upstream_group(&mut location.conf, line_no)?;
+                }
+                conf.locations.push(location);
             }
-            if key == "server.browsing_enabled" {
-                conf.browsing_enabled = enabled_values.contains(&value.to_string().as_str());
+            None => if let Some(line_no) = group_line {
+                Self::build_upstream_group(conf, line_no)?;
             }
+        }
+        Ok(())
+    }
+
+    fn parse_location(conf: &Conf, header: &str, line_no: usize) -> Result<Location, Box<dyn Error>> {
+        let matcher = match header.trim().strip_prefix("location") {
+            Some(matcher) => matcher,
+            None => return Err(format!("Unknown section [{}]. Line no. {}", header, line_no))?
+        };
+        let matcher = match LocationMatch::parse(matcher) {
+            Ok(m) => m,
+            Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+        };
+        let mut location_conf = conf.clone();
+        location_conf.locations.clear();
+        Ok(Location { matcher, conf: location_conf })
+    }
+
+    /// Keys configuring the virtual host itself which cannot be changed per location.
+    fn is_server_key(key: &str) -> bool {
+        ["server.port", "server.listen", "server.socket_mode", "server.domain", "server.alias", "server.default",
+            "server.workers", "server.max_connections_per_ip", "server.connection_overflow", "server.proxy_protocol"]
+            .contains(&key) || key.starts_with("https.") || key.starts_with("real_ip.")
+    }
+
+    /// Load balancer keys stored in the upstream group, other keys keep the group shared with the domain.
+    fn is_upstream_group_key(key: &str) -> bool {
+        key.starts_with("load_balancer.")
+            && !["load_balancer.enabled", "load_balancer.retries", "load_balancer.connect_timeout",
+                "load_balancer.read_timeout"].contains(&key)
+    }
+
+    /// Builds the upstream group from the load balancer settings of a section.
+    fn build_upstream_group(conf: &mut Conf, line_no: usize) -> Result<(), Box<dyn Error>> {
+        match (&conf.load_balancing_tls.cert, &conf.load_balancing_tls.key) {
+            (Some(_), None) => return Err(format!("load_balancer.tls_key is missing. Line no. {}", line_no))?,
+            (None, Some(_)) => return Err(format!("load_balancer.tls_cert is missing. Line no. {}", line_no))?,
+            _ => {}
+        }
+        let mut group = UpstreamGroup::new(
+            &conf.load_balancing_servers,
+            conf.load_balancing_strategy.clone(),
+            conf.load_balancing_hash_key.clone()
+        );
+        if conf.load_balancing_health_check.kind.is_some() {
+            group.health_check = Some(conf.load_balancing_health_check.clone());
+        }
+        // The PROXY protocol header describes only the first client of a connection
+        if conf.load_balancing_proxy_protocol.is_none() {
+            group.keepalive = conf.load_balancing_keepalive;
+        }
+        group.keepalive_timeout = conf.load_balancing_keepalive_timeout;
+        group.dns_ttl = conf.load_balancing_dns_ttl;
+        group.proxy_protocol = conf.load_balancing_proxy_protocol.clone();
+        if conf.load_balancing_servers.iter().any(|s| s.tls) {
+            group.tls = match TlsClient::new(&conf.load_balancing_tls) {
+                Ok(tls) => Some(tls),
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+        }
+        conf.upstream_group = Some(Arc::new(group));
+        Ok(())
+    }
 
-            if key == "logs.enabled" {
-                conf.logs_enabled = enabled_values.contains(&value.to_string().as_str());
+    fn list_name(key: &str) -> &str {
+        match key {
+            "redirect.exact" | "redirect.prefix" | "redirect.map" => "redirect",
+            "header.add" | "header.set" | "header.remove" => "header",
+            "access.allow" | "access.deny" => "access",
+            _ => key
+        }
+    }
+
+    /// Repeatable keys are inherited by locations, but the first occurrence in a location replaces the list.
+    fn reset_list(conf: &mut Conf, key: &str) {
+        match Self::list_name(key) {
+            "load_balancer.servers" => conf.load_balancing_servers.clear(),
+            "cache.pattern" => conf.cache_patterns.clear(),
+            "rewrite" => conf.rewrites.clear(),
+            "header" => conf.headers.clear(),
+            "access" => conf.access_rules.clear(),
+            "proxy.redirect" => conf.proxy_redirects.clear(),
+            "proxy.cookie_domain" => conf.proxy_cookie_domains.clear(),
+            "proxy.cookie_path" => conf.proxy_cookie_paths.clear(),
+            "redirect" => {
+                conf.redirects.clear();
+                conf.redirect_map.clear();
             }
-            if key == "logs.min_level" {
-                let levels = ["debug", "info", "error"];
-                if levels.contains(&value) {
-                    conf.logs_min_level = value.to_string();
-                } else {
-                    return Err(Box::new(ConfError::new(
-                        "Invalid min log level value. Line no. {}",
-                    )));
-                }
+            _ => {}
+        }
+    }
+
+    fn parse_line(conf: &mut Conf, key: &str, value: &str, line_no: usize) -> Result<(), Box<dyn Error>> {
+        let enabled_values = ["1", "true", "t", "enabled", "y", "yes"];
+
+        if key == "server.port" {
+            conf.port = Self::parse_u16(
+                value,
+                format!("Port is not valid integer. Line no. {}", line_no).as_str(),
+            )?;
+        }
+        if key == "server.listen" {
+            let listen = Self::parse_listen(
+                value,
+                format!("Invalid listen address. Line no. {}", line_no).as_str(),
+            )?;
+            conf.listen.push(listen);
+        }
+        if key == "server.socket_mode" {
+            let mode = match u32::from_str_radix(value, 8) {
+                Ok(mode) if mode <= 0o777 => mode,
+                _ => return Err(format!("Invalid socket mode. Line no. {}", line_no))?
+            };
+            conf.socket_mode = Some(mode);
+        }
+        if key == "server.dir" {
+            conf.dir = value.to_string();
+        }
+        if key == "server.workers" {
+            conf.workers = Self::parse_usize(
+                value,
+                format!("Workers is not valid integer. Line no. {}", line_no).as_str(),
+            )?;
+        }
+        if key == "server.max_connections_per_ip" {
+            conf.max_connections_per_ip = Self::parse_usize(
+                value,
+                format!("Max connections per IP is not valid integer. Line no. {}", line_no).as_str()
+            )?;
+        }
+        if key == "server.connection_overflow" {
+            conf.reject_overflow = match value {
+                "queue" => false,
+                "reject" => true,
+                _ => return Err(format!("Invalid connection overflow mode {}. Line no. {}", value, line_no))?
+            };
+        }
+        if key == "server.proxy_protocol" {
+            conf.proxy_protocol = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "server.timeout" {
+            let timeout = Self::parse_u16(
+                value,
+                format!("Timeout is not valid integer. Line no. {}", line_no).as_str(),
+            )?;
+            let timeout = u64::from(timeout.max(1));
+            conf.timeout = Duration::from_secs(timeout);
+        }
+        if key == "server.domain" {
+            conf.server_name = Self::parse_server_name(value, line_no)?;
+            conf.domain = value.to_string();
+        }
+        if key == "server.alias" {
+            let alias = Self::parse_server_name(value, line_no)?;
+            conf.aliases.push(alias);
+        }
+        if key == "server.default" {
+            conf.default_host = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "server.browsing_enabled" {
+            conf.browsing_enabled = enabled_values.contains(&value.to_string().as_str());
+        }
+
+        if key == "logs.enabled" {
+            conf.logs_enabled = enabled_values.contains(&value.to_string().as_str());
+        }
+        if key == "logs.min_level" {
+            let levels = ["debug", "info", "error"];
+            if levels.contains(&value) {
+                conf.logs_min_level = value.to_string();
+            } else {
+                return Err(Box::new(ConfError::new(
+                    "Invalid min log level value. Line no. {}",
+                )));
             }
-            if key == "logs.dir" {
-                let path = Path::new(value);
-                if path.exists() && path.is_dir() {
-                    conf.logs_dir = Some(path.into());
-                } else {
-                    if let Some(parent) = path.parent() {
-                        if fs::create_dir_all(parent).is_err() {
-                            return Err(format!("Invalid log dir. Line no. {}", line_no))?;
-                        }
+        }
+        if key == "logs.dir" {
+            let path = Path::new(value);
+            if path.exists() && path.is_dir() {
+                conf.logs_dir = Some(path.into());
+            } else {
+                if let Some(parent) = path.parent() {
+                    if fs::create_dir_all(parent).is_err() {
+                        return Err(format!("Invalid log dir. Line no. {}", line_no))?;
                     }
                 }
             }
+        }
 
-            if key == "load_balancer.enabled" {
-                conf.load_balancing_enabled = enabled_values.contains(&value.to_lowercase().as_str());
+        if key == "load_balancer.enabled" {
+            conf.load_balancing_enabled = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "load_balancer.servers" {
+            let servers = Self::parse_upstream_servers(value, line_no)?;
+            conf.load_balancing_servers.extend(servers);
+        }
+        if key == "load_balancer.strategy" {
+            conf.load_balancing_strategy = match BalanceStrategy::parse(value) {
+                Ok(strategy) => strategy,
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+        }
+        if key == "load_balancer.hash_key" {
+            conf.load_balancing_hash_key = match HashKey::parse(value) {
+                Ok(hash_key) => hash_key,
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+        }
+        if key == "load_balancer.retries" {
+            conf.load_balancing_retries = Self::parse_usize(
+                value,
+                format!("Load balancer retries is not valid integer. Line no. {}", line_no).as_str()
+            )?;
+        }
+        if key == "load_balancer.connect_timeout" {
+            let timeout = Self::parse_u16(value, format!("Connect timeout is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_connect_timeout = Duration::from_secs(u64::from(timeout.max(1)));
+        }
+        if key == "load_balancer.read_timeout" {
+            let timeout = Self::parse_u16(value, format!("Read timeout is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_read_timeout = Duration::from_secs(u64::from(timeout.max(1)));
+        }
+        if key == "load_balancer.proxy_protocol" {
+            conf.load_balancing_proxy_protocol = match value {
+                "off" => None,
+                "v1" => Some(ProxyProtocolVersion::V1),
+                "v2" => Some(ProxyProtocolVersion::V2),
+                _ => return Err(format!("Invalid PROXY protocol version {}. Line no. {}", value, line_no))?
+            };
+        }
+        if key == "load_balancer.health_check" {
+            conf.load_balancing_health_check.kind = match HealthCheck::parse_kind(value) {
+                Ok(kind) => kind,
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+        }
+        if key == "load_balancer.health_check_path" {
+            conf.load_balancing_health_check.path = value.to_string();
+        }
+        if key == "load_balancer.health_check_status" {
+            conf.load_balancing_health_check.status = value.to_lowercase();
+        }
+        if key == "load_balancer.health_check_interval" {
+            let interval = Self::parse_u16(value, format!("Health check interval is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_health_check.interval = Duration::from_secs(u64::from(interval.max(1)));
+        }
+        if key == "load_balancer.health_check_timeout" {
+            let timeout = Self::parse_u16(value, format!("Health check timeout is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_health_check.timeout = Duration::from_secs(u64::from(timeout.max(1)));
+        }
+        if key == "load_balancer.health_check_rise" {
+            let rise = Self::parse_u16(value, format!("Health check rise is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_health_check.rise = u32::from(rise);
+        }
+        if key == "load_balancer.health_check_fall" {
+            let fall = Self::parse_u16(value, format!("Health check fall is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_health_check.fall = u32::from(fall);
+        }
+        if key == "load_balancer.keepalive" {
+            conf.load_balancing_keepalive = Self::parse_usize(
+                value,
+                format!("Keep-alive connections is not valid integer. Line no. {}", line_no).as_str()
+            )?;
+        }
+        if key == "load_balancer.keepalive_timeout" {
+            let timeout = Self::parse_u16(value, format!("Keep-alive timeout is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_keepalive_timeout = Duration::from_secs(u64::from(timeout.max(1)));
+        }
+        if key == "load_balancer.dns_ttl" {
+            let ttl = Self::parse_u16(value, format!("DNS TTL is not valid integer. Line no. {}", line_no).as_str())?;
+            conf.load_balancing_dns_ttl = Duration::from_secs(u64::from(ttl));
+        }
+        if key == "load_balancer.tls_ca" {
+            conf.load_balancing_tls.ca = Some(value.to_string());
+        }
+        if key == "load_balancer.tls_cert" {
+            conf.load_balancing_tls.cert = Some(value.to_string());
+        }
+        if key == "load_balancer.tls_key" {
+            conf.load_balancing_tls.key = Some(value.to_string());
+        }
+        if key == "load_balancer.tls_server_name" {
+            conf.load_balancing_tls.server_name = Some(value.to_string());
+        }
+        if key == "load_balancer.tls_verify" {
+            conf.load_balancing_tls.verify = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "proxy.x_forwarded" {
+            conf.proxy_x_forwarded = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "proxy.forwarded" {
+            conf.proxy_forwarded = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "proxy.via" {
+            conf.proxy_via = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "proxy.redirect" {
+            match value {
+                "default" => conf.proxy_redirect_default = true,
+                "off" => {
+                    conf.proxy_redirect_default = false;
+                    conf.proxy_redirects.clear();
+                }
+                _ => conf.proxy_redirects.push(Self::parse_replacement(value, line_no)?)
+            }
+        }
+        if key == "proxy.cookie_domain" {
+            match value {
+                "off" => conf.proxy_cookie_domains.clear(),
+                _ => conf.proxy_cookie_domains.push(Self::parse_replacement(value, line_no)?)
+            }
+        }
+        if key == "proxy.cookie_path" {
+            match value {
+                "off" => conf.proxy_cookie_paths.clear(),
+                _ => conf.proxy_cookie_paths.push(Self::parse_replacement(value, line_no)?)
             }
-            if key == "load_balancer.servers" {
-                let server_addr = Self::parse_server_addr(value, line_no)?;
-                conf.load_balancing_servers.push(server_addr);
+        }
+
+        if key == "https.enabled" {
+            conf.https_enabled = enabled_values.contains(&value.to_string().as_str());
+        }
+        if key == "https.public_key" {
+            conf.https_pub_cert = match Path::new(value).is_file() {
+                true => value.to_string(),
+                false => {
+                    return Err(format!("Public key doesn't exist. Line no. {}", line_no))?
+                }
+            };
+        }
+        if key == "https.private_key" {
+            conf.https_private_key = match Path::new(value).is_file() {
+                true => value.to_string(),
+                false => {
+                    return Err(format!("Private key doesn't exist. Line no. {}", line_no))?
+                }
+            };
+        }
+
+        if key == "https.redirect_http" {
+            conf.https_redirect_http = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "https.redirect_port" {
+            conf.https_redirect_port = Self::parse_u16(
+                value,
+                format!("Redirect port is not valid integer. Line no. {}", line_no).as_str(),
+            )?;
+        }
+        if key == "https.redirect_status" {
+            conf.https_redirect_status = match value {
+                "301" => 301,
+                "302" => 302,
+                "307" => 307,
+                "308" => 308,
+                _ => return Err(format!("Invalid redirect status. Line no. {}", line_no))?
+            };
+        }
+        if key == "https.hsts_max_age" {
+            let max_age = match value.parse::<u64>() {
+                Ok(v) => v,
+                Err(_) => return Err(format!("HSTS max age is not valid integer. Line no. {}", line_no))?
+            };
+            conf.hsts_max_age = Some(max_age);
+        }
+        if key == "https.hsts_include_subdomains" {
+            conf.hsts_include_subdomains = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "https.hsts_preload" {
+            conf.hsts_preload = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+
+        if key == "php.enabled" {
+            conf.php_enabled = enabled_values.contains(&value.to_string().as_str());
+        }
+        if key == "php.index" {
+            conf.php_index = Some(value.to_string());
+        }
+        if key == "php.port" {
+            let port = Self::parse_u16(
+                value,
+                format!("PHP FPM/FastCGI port is not valid integer. Line no. {}", line_no).as_str(),
+            )?;
+            conf.php_port = Some(port);
+        }
+        if key == "php.socket" {
+            conf.php_socket = Some(value.to_string());
+        }
+
+        if key == "cache.enabled" {
+            conf.cache_enabled = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "cache.dir" {
+            let path = Path::new(value);
+            if path.exists() && path.is_dir() {
+                conf.cache_dir = Some(path.into());
+            } else {
+                return Err(format!("Invalid cache dir. Line no. {}", line_no))?;
             }
+        }
+        if key == "cache.pattern" {
+            conf.cache_patterns.push(value.to_string());
+        }
 
-            if key == "https.enabled" {
-                conf.https_enabled = enabled_values.contains(&value.to_string().as_str());
+        if key == "rewrite" {
+            let rule = match RewriteRule::parse(value) {
+                Ok(rule) => rule,
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+            conf.rewrites.push(rule);
+        }
+        if key == "try_files" {
+            let files: Vec<String> = value.split_whitespace().map(|f| f.to_string()).collect();
+            let fallback = files.last().map(|f| f.as_str()).unwrap_or_default();
+            if files.len() < 2 || (fallback.starts_with('=') && fallback != "=404") {
+                return Err(format!("Invalid try_files. Line no. {}", line_no))?;
             }
-            if key == "https.public_key" {
-                conf.https_pub_cert = match Path::new(value).is_file() {
-                    true => value.to_string(),
-                    false => {
-                        return Err(format!("Public key doesn't exist. Line no. {}", line_no))?
-                    }
-                };
+            conf.try_files = files;
+        }
+
+        if key == "redirect" || key == "redirect.exact" || key == "redirect.prefix" {
+            let kind = match key {
+                "redirect.exact" => "exact",
+                "redirect.prefix" => "prefix",
+                _ => "regex"
+            };
+            let rule = match RedirectRule::parse(kind, value) {
+                Ok(rule) => rule,
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+            conf.redirects.push(rule);
+        }
+        if key == "redirect.map" {
+            let (exact, rules) = load_redirect_map(value)?;
+            conf.redirect_map.extend(exact);
+            conf.redirects.extend(rules);
+        }
+        if key == "redirect.preserve_query" {
+            conf.redirect_preserve_query = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+
+        if key == "header.add" || key == "header.set" || key == "header.remove" {
+            let action = match key {
+                "header.add" => HeaderAction::Add,
+                "header.set" => HeaderAction::Set,
+                _ => HeaderAction::Remove
+            };
+            let rule = match HeaderRule::parse(action, value) {
+                Ok(rule) => rule,
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+            conf.headers.push(rule);
+        }
+
+        if key == "cors.allow_origins" {
+            conf.cors.origins = Cors::parse_list(value);
+        }
+        if key == "cors.allow_methods" {
+            conf.cors.methods = Cors::parse_list(&value.to_uppercase());
+        }
+        if key == "cors.allow_headers" {
+            conf.cors.headers = Cors::parse_list(value);
+        }
+        if key == "cors.allow_credentials" {
+            conf.cors.credentials = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        if key == "cors.max_age" {
+            conf.cors.max_age = match Cors::parse_max_age(value) {
+                Ok(age) => Some(age),
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+        }
+
+        if key == "auth.basic" {
+            let realm = value.trim_matches('"');
+            conf.auth_realm = match realm.eq_ignore_ascii_case("off") {
+                true => None,
+                false => Some(realm.to_string())
+            };
+        }
+        if key == "auth.htpasswd" {
+            conf.auth_users = load_htpasswd(value)?;
+        }
+
+        if key == "access.allow" || key == "access.deny" {
+            let rule = match AccessRule::parse(key == "access.allow", value) {
+                Ok(rule) => rule,
+                Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+            };
+            conf.access_rules.push(rule);
+        }
+        if key == "real_ip.header" {
+            conf.real_ip_header = value.to_string();
+        }
+        if key == "real_ip.trusted_proxies" {
+            conf.trusted_proxies.clear();
+            conf.trust_unix_proxy = false;
+            for proxy in value.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+                if proxy == "unix:" {
+                    conf.trust_unix_proxy = true;
+                    continue;
+                }
+                match Cidr::parse(proxy) {
+                    Ok(cidr) => conf.trusted_proxies.push(cidr),
+                    Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+                }
             }
-            if key == "https.private_key" {
-                conf.https_private_key = match Path::new(value).is_file() {
-                    true => value.to_string(),
-                    false => {
-                        return Err(format!("Private key doesn't exist. Line no. {}", line_no))?
+        }
+
+        if key.starts_with("rate_limit.") {
+            let mut limit = match &conf.rate_limit {
+                Some(limit) => RateLimit::clone(limit),
+                None => RateLimit::new(0.0, 0, RateLimitKey::Ip, false)
+            };
+            if key == "rate_limit.rate" {
+                limit.rate = match value {
+                    "off" => 0.0,
+                    _ => match RateLimit::parse_rate(value) {
+                        Ok(rate) => rate,
+                        Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
                     }
                 };
             }
-
-            if key == "php.enabled" {
-                conf.php_enabled = enabled_values.contains(&value.to_string().as_str());
+            if key == "rate_limit.burst" {
+                limit.burst = Self::parse_usize(
+                    value,
+                    format!("Rate limit burst is not valid integer. Line no. {}", line_no).as_str()
+                )? as u32;
             }
-            if key == "php.index" {
-                conf.php_index = Some(value.to_string());
+            if key == "rate_limit.key" {
+                limit.key = match RateLimit::parse_key(value) {
+                    Ok(key) => key,
+                    Err(e) => return Err(format!("{}. Line no. {}", e, line_no))?
+                };
             }
-            if key == "php.port" {
-                let port = Self::parse_u16(
-                    value,
-                    format!("PHP FPM/FastCGI port is not valid integer. Line no. {}", line_no).as_str(),
-                )?;
-                conf.php_port = Some(port);
+            if key == "rate_limit.mode" {
+                limit.delay = match value {
+                    "delay" => true,
+                    "reject" => false,
+                    _ => return Err(format!("Invalid rate limit mode {}. Line no. {}", value, line_no))?
+                };
             }
-            if key == "php.socket" {
-                conf.php_socket = Some(value.to_string());
+            conf.rate_limit = Some(Arc::new(limit));
+        }
+
+        if key == "mime.types" {
+            let path = match value {
+                "system" => Self::system_mime_types_path(),
+                _ => Some(value.to_string())
+            };
+            let path = match path {
+                Some(path) if Path::new(&path).is_file() => path,
+                _ => return Err(format!("Invalid mime types file. Line no. {}", line_no))?
+            };
+            conf.mime_types = Self::parse_mime_types(&path)?;
+        }
+        else if key == "mime.charset" {
+            conf.mime_charset = match value {
+                "" | "none" | "off" => None,
+                _ => Some(value.to_string())
+            };
+        }
+        else if key == "mime.nosniff" {
+            conf.mime_nosniff = enabled_values.contains(&value.to_lowercase().as_str());
+        }
+        else if let Some(ext) = key.strip_prefix("mime.") {
+            if ext.is_empty() || !value.contains('/') {
+                return Err(format!("Invalid mime type override. Line no. {}", line_no))?;
             }
+            conf.mime_overrides.insert(ext.to_lowercase(), value.to_string());
+        }
+
+        Ok(())
+    }
 
-            if key == "cache.enabled" {
-                conf.cache_enabled = enabled_values.contains(&value.to_lowercase().as_str());
+    fn parse_server_name(value: &str, line_no: usize) -> Result<ServerName, Box<dyn Error>> {
+        match ServerName::parse(value) {
+            Ok(name) => Ok(name),
+            Err(e) => Err(format!("{}. Line no. {}", e, line_no))?
+        }
+    }
+
+    fn system_mime_types_path() -> Option<String> {
+        let candidates = ["/etc/mime.types", "/etc/apache2/mime.types", "/etc/nginx/mime.types"];
+        candidates
+            .iter()
+            .find(|p| Path::new(p).is_file())
+            .map(|p| p.to_string())
+    }
+
+    fn parse_mime_types(path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        let mut types = HashMap::new();
+        let contents = fs::read_to_string(path)?;
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            if key == "cache.dir" {
-                let path = Path::new(value);
-                if path.exists() && path.is_dir() {
-                    conf.cache_dir = Some(path.into());
-                } else {
-                    return Err(format!("Invalid cache dir. Line no. {}", line_no))?;
+            let mut parts = line.split_whitespace();
+            let content_type = match parts.next() {
+                Some(t) if t.contains('/') => t,
+                _ => continue
+            };
+            for ext in parts {
+                let ext = ext.trim_end_matches(';');
+                if !ext.is_empty() {
+                    types.insert(ext.to_lowercase(), content_type.to_string());
                 }
             }
-            if key == "cache.pattern" {
-                conf.cache_patterns.push(value.to_string());
-            }
-
-            line_no += 1;
         }
-
-        Ok(())
+        Ok(types)
     }
 
     fn parse_path(path: &String) -> Result<String, Box<dyn Error>> {
@@ -237,33 +863,117 @@ impl ConfBuilder {
         }
     }
 
-    fn parse_server_addr(ip_string: &str, line_no: usize) -> Result<SocketAddr, Box<dyn Error>> {
-        let addr_port = ip_string.split(":").collect::<Vec<&str>>();
-        if addr_port.len() != 2 {
-            let msg =
-                format!("Invalid load balancer server address. Line no. {}", line_no).to_string();
-            return Err(msg)?;
-        }
-        let port = addr_port[1].trim().to_string();
-
-        let addr = match IpAddr::from_str(addr_port[0]) {
-            Ok(ip) => ip,
-            Err(_) => {
-                let msg = format!(
-                    "Invalid load balancer server IP address {} in line no. {}",
-                    addr_port[0], line_no
-                );
+    fn parse_listen(value: &str, msg: &str) -> Result<Listen, Box<dyn Error>> {
+        let mut parts = value.split_whitespace();
+        let addr = Self::parse_listen_addr(parts.next().unwrap_or_default(), msg)?;
+        let mut tls = None;
+        for flag in parts {
+            match flag {
+                "ssl" | "https" => tls = Some(true),
+                "plain" | "http" => tls = Some(false),
+                _ => return Err(format!("{}. Unknown flag '{}'", msg, flag))?
+            }
+        }
+        Ok(Listen { addr, tls })
+    }
+
+    fn parse_listen_addr(value: &str, msg: &str) -> Result<ListenAddr, Box<dyn Error>> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:") {
+            if cfg!(not(unix)) {
+                return Err(format!("Unix sockets are not supported on this platform: {}", path))?;
+            }
+            if path.is_empty() {
                 return Err(msg)?;
             }
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+        if let Ok(port) = value.parse::<u16>() {
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+            return Ok(ListenAddr::Tcp(addr));
+        }
+        if let Some(port) = value.strip_prefix("*:") {
+            let port = Self::parse_u16(port, msg)?;
+            let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port);
+            return Ok(ListenAddr::Tcp(addr));
+        }
+        match SocketAddr::from_str(value) {
+            Ok(addr) => Ok(ListenAddr::Tcp(addr)),
+            Err(_) => Err(msg)?
+        }
+    }
+
+    /// Parses `ADDRESS [weight=N] [max_fails=N] [fail_timeout=SECONDS]`, a host name with several addresses gives a server for each.
+    fn parse_upstream_servers(value: &str, line_no: usize) -> Result<Vec<UpstreamServer>, Box<dyn Error>> {
+        let mut parts = value.split_whitespace();
+        let addr = parts.next().unwrap_or_default();
+        let (tls, addr) = match addr.split_once("://") {
+            Some(("https", addr)) => (true, addr),
+            Some(("http", addr)) => (false, addr),
+            Some((scheme, _)) => return Err(format!("Unsupported server scheme {}. Line no. {}", scheme, line_no))?,
+            None => (false, addr)
         };
+        let (host, addrs) = Self::parse_server_addrs(addr, line_no)?;
+        let mut server = UpstreamServer::new(addrs[0]);
+        server.host = host;
+        server.tls = tls;
+        for param in parts {
+            match param.split_once('=') {
+                Some(("weight", weight)) => {
+                    server.weight = match weight.parse::<u32>() {
+                        Ok(weight) if weight > 0 => weight,
+                        _ => return Err(format!("Invalid server weight {}. Line no. {}", weight, line_no))?
+                    };
+                }
+                Some(("max_fails", max_fails)) => {
+                    server.max_fails = match max_fails.parse::<u32>() {
+                        Ok(max_fails) => max_fails,
+                        _ => return Err(format!("Invalid server max_fails {}. Line no. {}", max_fails, line_no))?
+                    };
+                }
+                Some(("fail_timeout", timeout)) => {
+                    server.fail_timeout = match timeout.parse::<u64>() {
+                        Ok(timeout) if timeout > 0 => Duration::from_secs(timeout),
+                        _ => return Err(format!("Invalid server fail_timeout {}. Line no. {}", timeout, line_no))?
+                    };
+                }
+                _ => return Err(format!("Unknown server parameter {}. Line no. {}", param, line_no))?
+            }
+        }
+        Ok(addrs.into_iter().map(|addr| UpstreamServer { addr, ..server.clone() }).collect())
+    }
+
+    /// Parses `FROM TO` of the proxy rewriting keys.
+    fn parse_replacement(value: &str, line_no: usize) -> Result<(String, String), Box<dyn Error>> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        match parts[..] {
+            [from, to] => Ok((from.to_string(), to.to_string())),
+            _ => Err(format!("Expected FROM TO, got {}. Line no. {}", value, line_no))?
+        }
+    }
 
-        let port = match port.parse() {
+    /// Parses `IP:PORT`, `[IPv6]:PORT` or `HOST:PORT`, a host is resolved to all its addresses.
+    fn parse_server_addrs(value: &str, line_no: usize) -> Result<(Option<String>, Vec<SocketAddr>), Box<dyn Error>> {
+        if let Ok(addr) = SocketAddr::from_str(value) {
+            return Ok((None, vec![addr]));
+        }
+        let (host, port) = match value.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && !host.contains([':', '[', ']']) => (host, port),
+            _ => return Err(format!("Invalid load balancer server address {}. Line no. {}", value, line_no))?
+        };
+        let port = match port.parse::<u16>() {
             Ok(port) => port,
-            Err(_) => {
-                return Err(format!("Invalid load balancer port {} in line no{}", line_no, port))?
-            }
+            Err(_) => return Err(format!("Invalid load balancer port {}. Line no. {}", port, line_no))?
         };
-
-        Ok(SocketAddr::new(addr, port))
+        let mut addrs: Vec<SocketAddr> = match (host, port).to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(e) => return Err(format!("Could not resolve {}. {}. Line no. {}", host, e, line_no))?
+        };
+        addrs.sort();
+        addrs.dedup();
+        if addrs.is_empty() {
+            return Err(format!("No address found for {}. Line no. {}", host, line_no))?;
+        }
+        Ok((Some(host.to_lowercase()), addrs))
     }
 }
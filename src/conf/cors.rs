@@ -0,0 +1,91 @@
+use std::error::Error;
+
+#[derive(Clone, Debug, Default)]
+pub struct Cors {
+    /// `*`, exact origins or wildcards like `https://*.example.com`.
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+impl Cors {
+    pub fn parse_list(value: &str) -> Vec<String> {
+        value
+            .split([',', ' '])
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    pub fn parse_max_age(value: &str) -> Result<u64, Box<dyn Error>> {
+        match value.parse::<u64>() {
+            Ok(age) => Ok(age),
+            Err(_) => Err(format!("Invalid CORS max age {}", value))?
+        }
+    }
+
+    /// Value of `Access-Control-Allow-Origin` for the request origin. With credentials the origin is echoed
+    /// instead of `*`.
+    pub fn allow_origin(&self, origin: &str) -> Option<String> {
+        let allowed = self.origins.iter().any(|o| {
+            if o == "*" {
+                return true;
+            }
+            match o.split_once("*.") {
+                Some((scheme, domain)) => origin
+                    .strip_prefix(scheme)
+                    .and_then(|host| host.strip_suffix(domain))
+                    .is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+                None => o.eq_ignore_ascii_case(origin)
+            }
+        });
+        match allowed {
+            true if self.origins.len() == 1 && self.origins[0] == "*" && !self.credentials => Some("*".to_string()),
+            true => Some(origin.to_string()),
+            false => None
+        }
+    }
+
+    /// Headers added to responses for an allowed origin.
+    pub fn response_headers(&self, origin: &str) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        let allow_origin = match self.allow_origin(origin) {
+            Some(allow_origin) => allow_origin,
+            None => return headers
+        };
+        if allow_origin != "*" {
+            headers.push(("Vary".to_string(), "Origin".to_string()));
+        }
+        headers.push(("Access-Control-Allow-Origin".to_string(), allow_origin));
+        if self.credentials {
+            headers.push(("Access-Control-Allow-Credentials".to_string(), "true".to_string()));
+        }
+        headers
+    }
+
+    /// Headers answering a preflight, requested headers are allowed when `cors.allow_headers` is not set.
+    pub fn preflight_headers(&self, origin: &str, request_headers: Option<&str>) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if self.allow_origin(origin).is_none() {
+            return headers;
+        }
+        let methods = match self.methods.is_empty() {
+            true => "GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS".to_string(),
+            false => self.methods.join(", ")
+        };
+        headers.push(("Access-Control-Allow-Methods".to_string(), methods));
+        let allow_headers = match self.headers.is_empty() {
+            true => request_headers.unwrap_or_default().to_string(),
+            false => self.headers.join(", ")
+        };
+        if !allow_headers.is_empty() {
+            headers.push(("Access-Control-Allow-Headers".to_string(), allow_headers));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.push(("Access-Control-Max-Age".to_string(), max_age.to_string()));
+        }
+        headers
+    }
+}
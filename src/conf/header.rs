@@ -0,0 +1,77 @@
+use std::error::Error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeaderAction {
+    Add,
+    Set,
+    Remove,
+}
+
+#[derive(Clone, Debug)]
+pub struct HeaderRule {
+    pub action: HeaderAction,
+    pub name: String,
+    pub value: String,
+    pub statuses: Vec<String>,
+    pub types: Vec<String>,
+}
+
+impl HeaderRule {
+    /// Parses `Name: value [status=200,3xx type=text/*]`. `remove` takes only a name.
+    pub fn parse(action: HeaderAction, value: &str) -> Result<HeaderRule, Box<dyn Error>> {
+        let mut value = value.trim();
+        let mut statuses = Vec::new();
+        let mut types = Vec::new();
+        if value.ends_with(']') && let Some(start) = value.rfind('[') {
+            for condition in value[start + 1..value.len() - 1].split_whitespace() {
+                match condition.split_once('=') {
+                    Some(("status", list)) => statuses = Self::list(list),
+                    Some(("type", list)) => types = Self::list(list),
+                    _ => Err(format!("Invalid header condition {}", condition))?
+                }
+            }
+            value = value[..start].trim();
+        }
+
+        let (name, value) = match action {
+            HeaderAction::Remove => (value, ""),
+            _ => match value.split_once(':') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => Err("Header requires name and value separated by ':'")?
+            }
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            Err(format!("Invalid header name {}", name))?
+        }
+
+        Ok(HeaderRule {
+            action,
+            name: name.to_string(),
+            value: value.to_string(),
+            statuses,
+            types,
+        })
+    }
+
+    fn list(value: &str) -> Vec<String> {
+        value
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// Checks status (`200`, `2xx`) and content type (`text/html`, `image/*`) conditions.
+    pub fn matches(&self, status: u32, content_type: &str) -> bool {
+        let status = status.to_string();
+        let status_matches = self.statuses.is_empty() || self.statuses.iter().any(|s| {
+            s.len() == 3 && s.chars().zip(status.chars()).all(|(p, c)| p == 'x' || p == c)
+        });
+        let mime = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+        let type_matches = self.types.is_empty() || self.types.iter().any(|t| match t.strip_suffix("/*") {
+            Some(prefix) => mime.split('/').next() == Some(prefix),
+            None => *t == mime
+        });
+        status_matches && type_matches
+    }
+}
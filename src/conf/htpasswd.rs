@@ -0,0 +1,102 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Loads `user:hash` lines of an htpasswd file.
+pub fn load_htpasswd(path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => Err(format!("Could not read htpasswd file {}: {}", path, e))?
+    };
+    let mut users = HashMap::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((user, hash)) if !user.is_empty() => { users.insert(user.to_string(), hash.to_string()); }
+            _ => Err(format!("Invalid htpasswd line {} in {}", idx + 1, path))?
+        }
+    }
+    Ok(users)
+}
+
+/// Verifies a password against a bcrypt (`$2y$`), SHA-1 (`{SHA}`) or APR1-MD5 (`$apr1$`) hash.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$2") {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    if let Some(digest) = hash.strip_prefix("{SHA}") {
+        let expected = STANDARD.encode(Sha1::digest(password.as_bytes()));
+        return constant_time_eq(expected.as_bytes(), digest.as_bytes());
+    }
+    if let Some(rest) = hash.strip_prefix("$apr1$") {
+        let salt = rest.split('$').next().unwrap_or_default();
+        return constant_time_eq(apr1(password, salt).as_bytes(), hash.as_bytes());
+    }
+    false
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn apr1(password: &str, salt: &str) -> String {
+    let password = password.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let mut alternate = Md5::new();
+    alternate.update(password);
+    alternate.update(salt);
+    alternate.update(password);
+    let alternate = alternate.finalize();
+
+    let mut ctx = Md5::new();
+    ctx.update(password);
+    ctx.update(b"$apr1$");
+    ctx.update(salt);
+    for chunk in password.chunks(16) {
+        ctx.update(&alternate[..chunk.len()]);
+    }
+    let mut i = password.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            ctx.update([0u8]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        i >>= 1;
+    }
+    let mut result = ctx.finalize();
+
+    for round in 0..1000 {
+        let mut ctx = Md5::new();
+        if round & 1 == 1 { ctx.update(password); } else { ctx.update(result); }
+        if round % 3 != 0 { ctx.update(salt); }
+        if round % 7 != 0 { ctx.update(password); }
+        if round & 1 == 1 { ctx.update(result); } else { ctx.update(password); }
+        result = ctx.finalize();
+    }
+
+    let mut out = format!("$apr1${}$", String::from_utf8_lossy(salt));
+    let groups = [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)];
+    for (a, b, c) in groups {
+        to64(&mut out, ((result[a] as u32) << 16) | ((result[b] as u32) << 8) | result[c] as u32, 4);
+    }
+    to64(&mut out, result[11] as u32, 2);
+    out
+}
+
+fn to64(out: &mut String, mut value: u32, len: usize) {
+    for _ in 0..len {
+        out.push(ITOA64[(value & 0x3f) as usize] as char);
+        value >>= 6;
+    }
+}
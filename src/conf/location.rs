@@ -0,0 +1,76 @@
+use crate::conf::Conf;
+use regex::{Regex, RegexBuilder};
+use std::error::Error;
+
+#[derive(Clone, Debug)]
+pub enum LocationMatch {
+    Prefix(String),
+    Regex(Regex),
+}
+
+impl LocationMatch {
+    /// Parses `/prefix`, `~ regex` or `~* case-insensitive regex`.
+    pub fn parse(value: &str) -> Result<LocationMatch, Box<dyn Error>> {
+        let value = value.trim();
+        let (pattern, case_insensitive) = if let Some(pattern) = value.strip_prefix("~*") {
+            (pattern.trim(), true)
+        } else if let Some(pattern) = value.strip_prefix('~') {
+            (pattern.trim(), false)
+        } else {
+            if !value.starts_with('/') {
+                return Err(format!("Location prefix must start with '/': {}", value))?;
+            }
+            return Ok(LocationMatch::Prefix(value.to_string()));
+        };
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build();
+        match regex {
+            Ok(regex) => Ok(LocationMatch::Regex(regex)),
+            Err(e) => Err(format!("Invalid location pattern {}: {}", pattern, e))?
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Location {
+    pub matcher: LocationMatch,
+    pub conf: Conf,
+}
+
+impl Location {
+    /// Serves the location from `dir` with the location prefix stripped from the path.
+    pub fn set_alias(&mut self, dir: &str, line_no: usize) -> Result<(), Box<dyn Error>> {
+        match &self.matcher {
+            LocationMatch::Prefix(prefix) => {
+                let prefix = prefix.trim_end_matches('/').to_string();
+                let dir = dir.trim_end_matches(['/', '\\']).to_string();
+                self.conf.dir = dir.clone();
+                self.conf.alias = Some((prefix, dir));
+                Ok(())
+            }
+            LocationMatch::Regex(_) => {
+                Err(format!("Alias requires a prefix location. Line no. {}", line_no))?
+            }
+        }
+    }
+}
+
+/// Longest matching prefix location, unless a regex location (checked in order) matches.
+pub fn find_location<'a>(locations: &'a [Location], path: &str) -> Option<&'a Location> {
+    let regex = locations.iter().find(|l| match &l.matcher {
+        LocationMatch::Regex(regex) => regex.is_match(path),
+        _ => false
+    });
+    if regex.is_some() {
+        return regex;
+    }
+    locations
+        .iter()
+        .filter_map(|l| match &l.matcher {
+            LocationMatch::Prefix(prefix) if path.starts_with(prefix.as_str()) => Some((prefix.len(), l)),
+            _ => None
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, l)| l)
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets are pruned when there are more keys than this.
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RateLimitKey {
+    Ip,
+    Vhost,
+    Path,
+    Header(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RateLimitDecision {
+    Allow,
+    Delay(Duration),
+    /// Seconds after which the client may retry.
+    Reject(u64),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: u32,
+    pub key: RateLimitKey,
+    pub delay: bool,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Clone for RateLimit {
+    /// Cloned limits have their own buckets.
+    fn clone(&self) -> Self {
+        RateLimit::new(self.rate, self.burst, self.key.clone(), self.delay)
+    }
+}
+
+impl RateLimit {
+    pub fn new(rate: f64, burst: u32, key: RateLimitKey, delay: bool) -> RateLimit {
+        RateLimit { rate, burst, key, delay, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Parses `10r/s`, `600r/m` or a number of requests per second.
+    pub fn parse_rate(value: &str) -> Result<f64, Box<dyn Error>> {
+        let value = value.trim();
+        let (count, per) = match value.split_once("r/") {
+            Some((count, "s")) => (count, 1.0),
+            Some((count, "m")) => (count, 60.0),
+            Some(_) => Err(format!("Invalid rate {}", value))?,
+            None => (value, 1.0)
+        };
+        match count.trim().parse::<f64>() {
+            Ok(count) if count > 0.0 => Ok(count / per),
+            _ => Err(format!("Invalid rate {}", value))?
+        }
+    }
+
+    pub fn parse_key(value: &str) -> Result<RateLimitKey, Box<dyn Error>> {
+        match value.trim() {
+            "ip" => Ok(RateLimitKey::Ip),
+            "vhost" => Ok(RateLimitKey::Vhost),
+            "path" => Ok(RateLimitKey::Path),
+            value => match value.strip_prefix("header:") {
+                Some(name) if !name.trim().is_empty() => Ok(RateLimitKey::Header(name.trim().to_string())),
+                _ => Err(format!("Invalid rate limit key {}", value))?
+            }
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        match self.delay {
+            true => 1.0,
+            false => self.burst.max(1) as f64
+        }
+    }
+
+    /// Takes a token for the key. In delay mode up to `burst` requests wait for tokens, otherwise `burst`
+    /// requests may pass at once.
+    pub fn check(&self, key: &str) -> RateLimitDecision {
+        self.check_at(key, Instant::now())
+    }
+
+    pub fn check_at(&self, key: &str, now: Instant) -> RateLimitDecision {
+        let capacity = self.capacity();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            let rate = self.rate;
+            buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < capacity);
+        }
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return RateLimitDecision::Allow;
+        }
+        let wait = (1.0 - bucket.tokens) / self.rate;
+        if self.delay && bucket.tokens > -(self.burst as f64) {
+            bucket.tokens -= 1.0;
+            return RateLimitDecision::Delay(Duration::from_secs_f64(wait));
+        }
+        RateLimitDecision::Reject(wait.ceil().max(1.0) as u64)
+    }
+}
@@ -0,0 +1,113 @@
+use crate::conf::rewrite::expand;
+use regex::Regex;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+#[derive(Clone, Debug)]
+pub enum RedirectMatch {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+#[derive(Clone, Debug)]
+pub struct RedirectRule {
+    pub status: u32,
+    pub matcher: RedirectMatch,
+    pub target: String,
+}
+
+impl RedirectRule {
+    /// Parses `STATUS SOURCE TARGET`, where kind is `exact`, `prefix` or `regex`.
+    pub fn parse(kind: &str, value: &str) -> Result<RedirectRule, Box<dyn Error>> {
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err("Redirect requires status, source and target")?;
+        }
+        Self::new(kind, parts[0], parts[1], parts[2])
+    }
+
+    pub fn new(kind: &str, status: &str, source: &str, target: &str) -> Result<RedirectRule, Box<dyn Error>> {
+        let status = match status.trim() {
+            "301" => 301,
+            "302" => 302,
+            "303" => 303,
+            "307" => 307,
+            "308" => 308,
+            _ => return Err(format!("Invalid redirect status {}", status))?
+        };
+        let source = source.trim();
+        let matcher = match kind {
+            "exact" => RedirectMatch::Exact(source.to_string()),
+            "prefix" => RedirectMatch::Prefix(source.to_string()),
+            "regex" => {
+                let pattern = match source.starts_with('^') {
+                    true => source.to_string(),
+                    false => format!("^{}", source)
+                };
+                match Regex::new(&pattern) {
+                    Ok(regex) => RedirectMatch::Regex(regex),
+                    Err(e) => return Err(format!("Invalid redirect pattern {}: {}", source, e))?
+                }
+            }
+            _ => return Err(format!("Unknown redirect type {}", kind))?
+        };
+        Ok(RedirectRule {
+            status,
+            matcher,
+            target: target.trim().to_string(),
+        })
+    }
+
+    /// Redirect URL for the path (without query string) if the rule matches.
+    pub fn location(&self, path: &str) -> Option<String> {
+        match &self.matcher {
+            RedirectMatch::Exact(source) if source == path => Some(self.target.clone()),
+            RedirectMatch::Prefix(source) => {
+                let rest = path.strip_prefix(source.as_str())?;
+                // `/docs` covers `/docs/a` but not `/docsfoo`
+                if !source.ends_with('/') && !rest.is_empty() && !rest.starts_with('/') {
+                    return None;
+                }
+                Some(format!("{}{}", self.target, rest))
+            }
+            RedirectMatch::Regex(regex) => {
+                let caps = regex.captures(path)?;
+                Some(expand(&self.target, Some(&caps), path, "", path))
+            }
+            _ => None
+        }
+    }
+}
+
+pub type RedirectMap = (HashMap<String, RedirectRule>, Vec<RedirectRule>);
+
+/// Loads a CSV file with `source,target[,status[,type]]` lines. Status defaults to 301 and type to `exact`.
+/// Exact redirects are returned as a map, the others as a list in file order.
+pub fn load_redirect_map(path: &str) -> Result<RedirectMap, Box<dyn Error>> {
+    let mut exact = HashMap::new();
+    let mut rules = Vec::new();
+    let contents = fs::read_to_string(path)?;
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let columns: Vec<&str> = line.split(',').map(|c| c.trim()).collect();
+        if columns.len() < 2 || columns.len() > 4 {
+            Err(format!("Invalid redirect map line {} in {}", idx + 1, path))?
+        }
+        let status = columns.get(2).copied().unwrap_or("301");
+        let kind = columns.get(3).copied().unwrap_or("exact");
+        let rule = match RedirectRule::new(kind, status, columns[0], columns[1]) {
+            Ok(rule) => rule,
+            Err(e) => return Err(format!("{}. Redirect map line {} in {}", e, idx + 1, path))?
+        };
+        match kind {
+            "exact" => { exact.insert(columns[0].to_string(), rule); }
+            _ => rules.push(rule)
+        }
+    }
+    Ok((exact, rules))
+}
@@ -0,0 +1,115 @@
+use regex::{Captures, Regex};
+use std::error::Error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RewriteFlag {
+    /// Continue with the next rule
+    None,
+    /// Stop and match locations again with the new URI
+    Last,
+    /// Stop and stay in the current location
+    Break,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileTest {
+    File,
+    Dir,
+    Exists,
+}
+
+#[derive(Clone, Debug)]
+pub struct RewriteCond {
+    pub negate: bool,
+    pub test: FileTest,
+}
+
+#[derive(Clone, Debug)]
+pub struct RewriteRule {
+    pub pattern: Regex,
+    pub replacement: String,
+    pub conditions: Vec<RewriteCond>,
+    pub flag: RewriteFlag,
+}
+
+impl RewriteRule {
+    /// Parses `PATTERN REPLACEMENT [last|break] [!-f] [!-d] [!-e]`.
+    /// Conditions test the file the current URI maps to.
+    pub fn parse(value: &str) -> Result<RewriteRule, Box<dyn Error>> {
+        let mut parts = value.split_whitespace();
+        let (pattern, replacement) = match (parts.next(), parts.next()) {
+            (Some(p), Some(r)) => (p, r),
+            _ => return Err("Rewrite requires pattern and replacement")?
+        };
+        let pattern = match Regex::new(pattern) {
+            Ok(p) => p,
+            Err(e) => return Err(format!("Invalid rewrite pattern {}: {}", pattern, e))?
+        };
+
+        let mut flag = RewriteFlag::None;
+        let mut conditions = Vec::new();
+        for part in parts {
+            match part {
+                "last" => flag = RewriteFlag::Last,
+                "break" => flag = RewriteFlag::Break,
+                _ => {
+                    let (negate, test) = match part.strip_prefix('!') {
+                        Some(test) => (true, test),
+                        None => (false, part)
+                    };
+                    let test = match test {
+                        "-f" => FileTest::File,
+                        "-d" => FileTest::Dir,
+                        "-e" => FileTest::Exists,
+                        _ => return Err(format!("Unknown rewrite flag or condition '{}'", part))?
+                    };
+                    conditions.push(RewriteCond { negate, test });
+                }
+            }
+        }
+
+        Ok(RewriteRule {
+            pattern,
+            replacement: replacement.to_string(),
+            conditions,
+            flag,
+        })
+    }
+}
+
+/// Expands `$1`..`$9` with captures and `$uri`, `$query` (or `$args`) and `$request_uri` with request values.
+pub fn expand(template: &str, caps: Option<&Captures>, uri: &str, query: &str, request_uri: &str) -> String {
+    let mut out = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        let rest = &template[idx + 1..];
+        if let Some(digit) = rest.chars().next().and_then(|d| d.to_digit(10)) {
+            if let Some(m) = caps.and_then(|c| c.get(digit as usize)) {
+                out.push_str(m.as_str());
+            }
+            chars.next();
+            continue;
+        }
+        let name_len = rest.chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count();
+        let value = match &rest[..name_len] {
+            "uri" => Some(uri),
+            "query" | "args" => Some(query),
+            "request_uri" => Some(request_uri),
+            _ => None
+        };
+        match value {
+            Some(value) => {
+                out.push_str(value);
+                for _ in 0..name_len {
+                    chars.next();
+                }
+            }
+            None => out.push(c)
+        }
+    }
+    out
+}
@@ -0,0 +1,87 @@
+use regex::Regex;
+use std::error::Error;
+
+#[derive(Clone, Debug)]
+pub enum ServerName {
+    Exact(String),
+    Wildcard(String),
+    Suffix(String),
+    Regex(Regex),
+}
+
+impl ServerName {
+    /// Parses `example.com`, `*.example.com` (subdomains only), `.example.com`
+    /// (domain and subdomains) or `~regex` server names.
+    pub fn parse(value: &str) -> Result<ServerName, Box<dyn Error>> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("Server name cannot be empty")?;
+        }
+        if let Some(pattern) = value.strip_prefix('~') {
+            let regex = match Regex::new(format!("(?i){}", pattern.trim()).as_str()) {
+                Ok(r) => r,
+                Err(e) => return Err(format!("Invalid server name pattern {}: {}", pattern, e))?
+            };
+            return Ok(ServerName::Regex(regex));
+        }
+        let value = value.to_lowercase();
+        if let Some(suffix) = value.strip_prefix('*') {
+            if !suffix.starts_with('.') || suffix.len() < 2 {
+                return Err(format!("Invalid wildcard server name {}", value))?;
+            }
+            return Ok(ServerName::Wildcard(suffix.to_string()));
+        }
+        if value.starts_with('.') {
+            return Ok(ServerName::Suffix(value));
+        }
+        Ok(ServerName::Exact(value))
+    }
+
+    /// Host must be normalized with `normalize_host`.
+    pub fn matches(&self, host: &str) -> bool {
+        match self {
+            ServerName::Exact(name) => name == host,
+            ServerName::Wildcard(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
+            ServerName::Suffix(suffix) => host.ends_with(suffix.as_str()) || host == &suffix[1..],
+            ServerName::Regex(regex) => regex.is_match(host),
+        }
+    }
+
+    /// Rank of a matching name, lower wins: exact names, then wildcards by length, then regexes.
+    fn rank(&self, host: &str) -> Option<(u8, usize)> {
+        if !self.matches(host) {
+            return None;
+        }
+        Some(match self {
+            ServerName::Exact(_) => (0, 0),
+            ServerName::Wildcard(suffix) | ServerName::Suffix(suffix) => (1, usize::MAX - suffix.len()),
+            ServerName::Regex(_) => (2, 0),
+        })
+    }
+}
+
+/// Item of the best matching name with nginx precedence: the exact name, the longest wildcard,
+/// then the first matching regex.
+pub fn best_match<'a, T>(names: impl Iterator<Item = (T, &'a ServerName)>, host: &str) -> Option<T> {
+    names
+        .filter_map(|(item, name)| name.rank(host).map(|rank| (rank, item)))
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, item)| item)
+}
+
+/// Lowercases host and strips port and trailing dot, e.g. `Example.com.:8080` -> `example.com`.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = if host.starts_with('[') {
+        match host.find(']') {
+            Some(idx) => &host[..idx + 1],
+            None => host
+        }
+    } else {
+        match host.rsplit_once(':') {
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+            _ => host
+        }
+    };
+    host.trim_end_matches('.').to_lowercase()
+}
@@ -0,0 +1,464 @@
+#[cfg(test)]
+mod tests {
+    use crate::conf::access::{is_allowed, real_ip, AccessRule, Cidr};
+    use crate::conf::cors::Cors;
+    use crate::conf::header::{HeaderAction, HeaderRule};
+    use crate::conf::htpasswd::verify_password;
+    use crate::conf::listen_addr::ListenAddr;
+    use crate::conf::rate_limit::{RateLimit, RateLimitDecision, RateLimitKey};
+    use crate::conf::rewrite::{expand, RewriteFlag, RewriteRule};
+    use crate::conf::upstream::{BalanceStrategy, HashKey, HealthCheck, IdlePool, UpstreamGroup, UpstreamServer};
+    use crate::conf::server_name::{best_match, normalize_host, ServerName};
+    use crate::conf::Conf;
+    use std::net::{IpAddr, SocketAddr};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn tcp(addr: &str) -> (ListenAddr, bool) {
+        (ListenAddr::Tcp(addr.parse::<SocketAddr>().unwrap()), false)
+    }
+
+    #[test]
+    fn listen_addrs_should_default_to_localhost() {
+        let conf = Conf::new(args(&["", "-p", "8080"])).unwrap();
+
+        assert_eq!(conf.listen_addrs(), vec![tcp("127.0.0.1:8080")]);
+    }
+
+    #[test]
+    fn listen_arg_should_accept_ipv4_ipv6_and_port() {
+        let conf = Conf::new(args(&["", "-l", "0.0.0.0:80,[::]:443,8080"])).unwrap();
+
+        assert_eq!(conf.listen_addrs(), vec![
+            tcp("0.0.0.0:80"),
+            tcp("[::]:443"),
+            tcp("0.0.0.0:8080"),
+        ]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_arg_should_accept_unix_socket() {
+        let conf = Conf::new(args(&["", "-l", "unix:/run/stormsrv/app.sock"])).unwrap();
+
+        assert_eq!(conf.listen_addrs(), vec![(ListenAddr::Unix("/run/stormsrv/app.sock".into()), false)]);
+    }
+
+    #[test]
+    fn listen_arg_should_accept_tls_flags() {
+        let path = std::env::temp_dir().join("storm_listen_tls_test.conf");
+        let key = std::env::temp_dir().join("storm_listen_tls_test.pem");
+        std::fs::write(&key, "").unwrap();
+        let key = key.to_str().unwrap();
+        std::fs::write(&path, format!("https.public_key = {}\nhttps.private_key = {}\n", key, key)).unwrap();
+        let path = path.to_str().unwrap();
+        let conf = Conf::new(args(&["", "-f", path, "-l", "0.0.0.0:80 plain,0.0.0.0:443 ssl"])).unwrap();
+
+        assert_eq!(conf.listen_addrs(), vec![
+            tcp("0.0.0.0:80"),
+            (ListenAddr::Tcp("0.0.0.0:443".parse::<SocketAddr>().unwrap()), true),
+        ]);
+        assert!(Conf::new(args(&["", "-l", "0.0.0.0:443 ssl"])).is_err());
+    }
+
+    #[test]
+    fn listen_arg_should_return_error() {
+        let conf = Conf::new(args(&["", "-l", "localhost:80"]));
+
+        assert!(conf.is_err());
+    }
+
+    #[test]
+    fn normalize_host_should_strip_port_and_lowercase() {
+        assert_eq!(normalize_host("Example.COM:8080"), "example.com");
+        assert_eq!(normalize_host("example.com."), "example.com");
+        assert_eq!(normalize_host("[::1]:443"), "[::1]");
+    }
+
+    #[test]
+    fn server_name_should_match_wildcards_and_regex() {
+        let wildcard = ServerName::parse("*.example.com").unwrap();
+        let suffix = ServerName::parse(".example.com").unwrap();
+        let regex = ServerName::parse("~^www\\d+\\.example\\.com$").unwrap();
+
+        assert!(wildcard.matches("api.example.com"));
+        assert!(!wildcard.matches("example.com"));
+        assert!(suffix.matches("example.com"));
+        assert!(suffix.matches("a.b.example.com"));
+        assert!(regex.matches("www12.example.com"));
+        assert!(!regex.matches("www.example.com"));
+    }
+
+    #[test]
+    fn best_match_should_prefer_exact_then_longest_wildcard_then_regex() {
+        let names: Vec<ServerName> = ["~^.*$", ".example.com", "*.api.example.com", "www.example.com", "~^api"]
+            .iter()
+            .map(|n| ServerName::parse(n).unwrap())
+            .collect();
+        let find = |host: &str| best_match(names.iter().enumerate(), host);
+
+        assert_eq!(find("www.example.com"), Some(3));
+        assert_eq!(find("v1.api.example.com"), Some(2));
+        assert_eq!(find("example.com"), Some(1));
+        assert_eq!(find("api.other.org"), Some(0));
+    }
+
+    #[test]
+    fn location_should_prefer_regex_over_longest_prefix() {
+        let path = std::env::temp_dir().join("storm_location_test.conf");
+        std::fs::write(&path, "server.dir = /srv\n\
+            [location /api]\n\
+            server.dir = /srv/api\n\
+            [location /api/v2]\n\
+            location.alias = /srv/v2\n\
+            [location ~* \\.php$]\n\
+            php.index = index.php\n").unwrap();
+        let conf = Conf::new(args(&["", "-f", path.to_str().unwrap()])).unwrap();
+
+        assert_eq!(conf.location("/").dir, "/srv");
+        assert_eq!(conf.location("/api/users").dir, "/srv/api");
+        assert_eq!(conf.location("/api/v2/users").alias, Some(("/api/v2".to_string(), "/srv/v2".to_string())));
+        assert_eq!(conf.location("/api/INDEX.PHP").php_index, Some("index.php".to_string()));
+    }
+
+    #[test]
+    fn alias_should_replace_whole_path_segments() {
+        let path = std::env::temp_dir().join("storm_alias_test.conf");
+        std::fs::write(&path, "server.dir = /srv\n\
+            [location /static]\n\
+            location.alias = /srv/assets/\n").unwrap();
+        let conf = Conf::new(args(&["", "-f", path.to_str().unwrap()])).unwrap();
+        let location = conf.location("/staticfoo/x");
+
+        assert_eq!(location.file_path("/static/app.css").unwrap(), PathBuf::from("/srv/assets/app.css"));
+        assert_eq!(location.file_path("/static").unwrap(), PathBuf::from("/srv/assets"));
+        assert!(!location.file_path("/staticfoo/x").unwrap().starts_with("/srv/assetsfoo"));
+    }
+
+    #[test]
+    fn dir_arg_should_apply_to_locations_without_own_dir() {
+        let path = std::env::temp_dir().join("storm_location_dir_test.conf");
+        std::fs::write(&path, "server.dir = /srv\n\
+            [location /api]\n\
+            server.dir = /srv/api\n\
+            [location /static]\n\
+            cache.enabled = yes\n").unwrap();
+        let dir = std::env::temp_dir().to_string_lossy().to_string();
+        let conf = Conf::new(args(&["", "-f", path.to_str().unwrap(), "-d", dir.as_str()])).unwrap();
+
+        assert_eq!(conf.location("/").dir, dir);
+        assert_eq!(conf.location("/api/users").dir, "/srv/api");
+        assert_eq!(conf.location("/static/app.js").dir, dir);
+    }
+
+    #[test]
+    fn location_should_reject_server_keys() {
+        let path = std::env::temp_dir().join("storm_location_error_test.conf");
+        std::fs::write(&path, "[location /api]\nserver.port = 8080\n").unwrap();
+
+        assert!(Conf::new(args(&["", "-f", path.to_str().unwrap()])).is_err());
+    }
+
+    #[test]
+    fn rewrite_rule_should_expand_captures_and_variables() {
+        let rule = RewriteRule::parse("^/blog/(\\d+)/(.*)$ /index.php?id=$1&slug=$2&$query last !-f").unwrap();
+        let caps = rule.pattern.captures("/blog/12/hello").unwrap();
+
+        assert_eq!(rule.flag, RewriteFlag::Last);
+        assert_eq!(rule.conditions.len(), 1);
+        assert_eq!(
+            expand(&rule.replacement, Some(&caps), "/blog/12/hello", "a=1", "/blog/12/hello?a=1"),
+            "/index.php?id=12&slug=hello&a=1"
+        );
+        assert_eq!(expand("$uri/ $unknown", None, "/x", "", "/x"), "/x/ $unknown");
+    }
+
+    #[test]
+    fn redirect_should_match_exact_prefix_and_regex_rules() {
+        let path = std::env::temp_dir().join("storm_redirect_test.conf");
+        let map = std::env::temp_dir().join("storm_redirect_test.csv");
+        std::fs::write(&map, "# source,target,status\n/old.html,/new.html,302\n").unwrap();
+        std::fs::write(&path, format!(
+            "redirect = 301 /blog/(\\d+) https://blog.example.com/post/$1\nredirect.prefix = 308 /docs https://docs.example.com\nredirect.map = {}\n",
+            map.to_str().unwrap()
+        )).unwrap();
+
+        let conf = Conf::new(args(&["", "-f", path.to_str().unwrap()])).unwrap();
+
+        assert_eq!(conf.redirect("/old.html", ""), Some((302, "/new.html".to_string())));
+        assert_eq!(conf.redirect("/blog/7", "a=1"), Some((301, "https://blog.example.com/post/7?a=1".to_string())));
+        assert_eq!(conf.redirect("/docs/intro", ""), Some((308, "https://docs.example.com/intro".to_string())));
+        assert_eq!(conf.redirect("/docsfoo", ""), None);
+        assert_eq!(conf.redirect("/about", ""), None);
+    }
+
+    #[test]
+    fn header_rule_should_parse_conditions() {
+        let rule = HeaderRule::parse(HeaderAction::Set, "Cache-Control: public, max-age=86400 [status=200,3xx type=image/*]").unwrap();
+
+        assert_eq!(rule.name, "Cache-Control");
+        assert_eq!(rule.value, "public, max-age=86400");
+        assert!(rule.matches(200, "image/png"));
+        assert!(rule.matches(304, "image/svg+xml; charset=utf-8"));
+        assert!(!rule.matches(404, "image/png"));
+        assert!(!rule.matches(200, "text/html"));
+        assert!(HeaderRule::parse(HeaderAction::Add, "X-Frame-Options").is_err());
+    }
+
+    #[test]
+    fn verify_password_should_support_htpasswd_hashes() {
+        let bcrypt = bcrypt::hash("secret", 4).unwrap();
+
+        assert!(verify_password("secret", &bcrypt));
+        assert!(verify_password("secret", "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ="));
+        assert!(verify_password("secret", "$apr1$s4ltS4lt$.ZAcsAOvz4MyRc8b10ynm."));
+        assert!(verify_password("a-much-longer-password-over-16", "$apr1$ab$esott8aZeMzmGKXvf6nVF1"));
+        assert!(!verify_password("wrong", "$apr1$s4ltS4lt$.ZAcsAOvz4MyRc8b10ynm."));
+        assert!(!verify_password("secret", "secret"));
+    }
+
+    #[test]
+    fn access_rules_should_match_cidr_ranges_in_order() {
+        let rules = vec![
+            AccessRule::parse(true, "192.168.10.0/24").unwrap(),
+            AccessRule::parse(true, "2001:db8::/32").unwrap(),
+            AccessRule::parse(false, "all").unwrap(),
+        ];
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        assert!(is_allowed(&rules, ip("192.168.10.7")));
+        assert!(is_allowed(&rules, ip("::ffff:192.168.10.7")));
+        assert!(is_allowed(&rules, ip("2001:db8:1::1")));
+        assert!(!is_allowed(&rules, ip("192.168.11.7")));
+        assert!(!is_allowed(&rules, None));
+        assert!(is_allowed(&[], ip("10.0.0.1")));
+        assert!(AccessRule::parse(true, "10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn real_ip_should_skip_trusted_proxies() {
+        let trusted = vec![Cidr::parse("10.0.0.0/8").unwrap()];
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+        let header = Some("203.0.113.9, 198.51.100.1, 10.0.0.2");
+
+        assert_eq!(real_ip(ip("10.0.0.1"), false, header, &trusted), ip("198.51.100.1"));
+        assert_eq!(real_ip(ip("198.51.100.5"), false, header, &trusted), ip("198.51.100.5"));
+        assert_eq!(real_ip(None, true, Some("203.0.113.9"), &trusted), ip("203.0.113.9"));
+        assert_eq!(real_ip(ip("10.0.0.1"), false, Some("bogus"), &trusted), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn rate_limit_should_allow_burst_and_refill() {
+        let limit = RateLimit::new(RateLimit::parse_rate("2r/s").unwrap(), 3, RateLimitKey::Ip, false);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limit.check_at("a", start), RateLimitDecision::Allow);
+        }
+        assert_eq!(limit.check_at("a", start), RateLimitDecision::Reject(1));
+        assert_eq!(limit.check_at("b", start), RateLimitDecision::Allow);
+        assert_eq!(limit.check_at("a", start + Duration::from_millis(500)), RateLimitDecision::Allow);
+
+        let delayed = RateLimit::new(1.0, 1, RateLimitKey::Ip, true);
+        assert_eq!(delayed.check_at("a", start), RateLimitDecision::Allow);
+        assert_eq!(delayed.check_at("a", start), RateLimitDecision::Delay(Duration::from_secs(1)));
+        assert_eq!(delayed.check_at("a", start), RateLimitDecision::Reject(2));
+        assert_eq!(RateLimit::parse_rate("60r/m").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn cors_should_match_allowed_origins() {
+        let mut cors = Cors {
+            origins: Cors::parse_list("https://app.example.com, https://*.example.org"),
+            ..Cors::default()
+        };
+
+        assert_eq!(cors.allow_origin("https://app.example.com"), Some("https://app.example.com".to_string()));
+        assert_eq!(cors.allow_origin("https://a.b.example.org"), Some("https://a.b.example.org".to_string()));
+        assert_eq!(cors.allow_origin("https://example.org"), None);
+        assert_eq!(cors.allow_origin("http://app.example.com"), None);
+
+        cors.origins = vec!["*".to_string()];
+        assert_eq!(cors.allow_origin("https://any.test"), Some("*".to_string()));
+        cors.credentials = true;
+        assert_eq!(cors.allow_origin("https://any.test"), Some("https://any.test".to_string()));
+    }
+
+    fn upstream_group(weights: &[u32], strategy: BalanceStrategy) -> Arc<UpstreamGroup> {
+        let servers: Vec<UpstreamServer> = weights
+            .iter()
+            .enumerate()
+            .map(|(idx, weight)| UpstreamServer {
+                weight: *weight,
+                ..UpstreamServer::new(format!("127.0.0.1:{}", 8000 + idx).parse().unwrap())
+            })
+            .collect();
+        Arc::new(UpstreamGroup::new(&servers, strategy, HashKey::Ip))
+    }
+
+    #[test]
+    fn upstream_group_should_balance_by_strategy() {
+        let group = upstream_group(&[1, 1, 1], BalanceStrategy::RoundRobin);
+        let picks: Vec<usize> = (0..4).map(|_| group.select("", &[]).unwrap().index).collect();
+        assert_eq!(picks, vec![0, 1, 2, 0]);
+
+        let group = upstream_group(&[5, 1, 1], BalanceStrategy::Weighted);
+        let picks: Vec<usize> = (0..7).map(|_| group.select("", &[]).unwrap().index).collect();
+        assert_eq!(picks, vec![0, 0, 1, 0, 2, 0, 0]);
+
+        let group = upstream_group(&[1, 1], BalanceStrategy::LeastConnections);
+        let busy = group.select("", &[]).unwrap();
+        assert_ne!(group.select("", &[]).unwrap().index, busy.index);
+        assert_eq!(group.upstreams[busy.index].active(), 1);
+        drop(busy);
+        assert_eq!(group.upstreams.iter().map(|u| u.active()).sum::<usize>(), 0);
+
+        let group = upstream_group(&[1, 1, 1], BalanceStrategy::Hash);
+        let first = group.select("203.0.113.7", &[]).unwrap().index;
+        assert!((0..10).all(|_| group.select("203.0.113.7", &[]).unwrap().index == first));
+
+        assert!(upstream_group(&[], BalanceStrategy::RoundRobin).select("", &[]).is_none());
+    }
+
+    #[test]
+    fn health_checks_should_change_state_after_thresholds() {
+        let group = upstream_group(&[1, 1], BalanceStrategy::RoundRobin);
+        let check = HealthCheck { status: "200,3xx".to_string(), rise: 2, fall: 2, ..HealthCheck::default() };
+        let upstream = &group.upstreams[0];
+
+        assert_eq!(upstream.record_check(false, &check), None);
+        assert_eq!(upstream.record_check(false, &check), Some(false));
+        assert!((0..4).all(|_| group.select("", &[]).unwrap().index == 1));
+        assert_eq!(upstream.record_check(true, &check), None);
+        assert_eq!(upstream.record_check(true, &check), Some(true));
+        assert!(check.status_matches(200) && check.status_matches(302) && !check.status_matches(204));
+    }
+
+    #[test]
+    fn failures_should_eject_upstream_until_fail_timeout() {
+        let server = UpstreamServer {
+            max_fails: 2,
+            fail_timeout: Duration::from_secs(10),
+            ..UpstreamServer::new("127.0.0.1:8000".parse().unwrap())
+        };
+        let group = Arc::new(UpstreamGroup::new(&[server], BalanceStrategy::RoundRobin, HashKey::Ip));
+        let upstream = &group.upstreams[0];
+        let start = Instant::now();
+
+        assert!(!upstream.record_failure(start));
+        assert!(upstream.record_failure(start + Duration::from_secs(1)));
+        assert!(upstream.is_ejected(start + Duration::from_secs(5)));
+        assert!(group.select("", &[]).is_none());
+        assert_eq!(upstream.ejected_for(start + Duration::from_secs(5)), Some(6));
+
+        // half-open after the timeout, one more failure ejects again
+        assert!(!upstream.is_ejected(start + Duration::from_secs(12)));
+        assert!(upstream.record_failure(start + Duration::from_secs(12)));
+        upstream.record_success();
+        assert!(!upstream.record_failure(start + Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn https_servers_should_build_tls_client() {
+        let path = std::env::temp_dir().join("storm_upstream_tls_test.conf");
+        std::fs::write(&path, "load_balancer.servers = https://127.0.0.1:8443 weight=2\n\
+            load_balancer.servers = http://127.0.0.1:8080\n\
+            load_balancer.tls_server_name = backend.internal\n\
+            load_balancer.tls_verify = no\n").unwrap();
+        let conf = Conf::new(args(&["", "-f", path.to_str().unwrap()])).unwrap();
+        let group = conf.upstream_group.unwrap();
+
+        assert!(conf.load_balancing_servers[0].tls);
+        assert_eq!(conf.load_balancing_servers[0].weight, 2);
+        assert!(!conf.load_balancing_servers[1].tls);
+        let tls = group.tls.as_ref().unwrap();
+        assert_eq!(tls.server_name(&conf.load_balancing_servers[0], "127.0.0.1:8443".parse().unwrap()).to_str(), "backend.internal");
+
+        std::fs::write(&path, "load_balancer.servers = ftp://127.0.0.1:21\n").unwrap();
+        assert!(Conf::new(args(&["", "-f", path.to_str().unwrap()])).is_err());
+        std::fs::write(&path, "load_balancer.servers = https://127.0.0.1:8443\n\
+            load_balancer.tls_ca = /nonexistent/ca.pem\n").unwrap();
+        assert!(Conf::new(args(&["", "-f", path.to_str().unwrap()])).is_err());
+        std::fs::write(&path, "load_balancer.servers = https://127.0.0.1:8443\n\
+            load_balancer.tls_cert = client.pem\n").unwrap();
+        let error = Conf::new(args(&["", "-f", path.to_str().unwrap()])).err().unwrap();
+        assert!(error.to_string().contains("load_balancer.tls_key"));
+    }
+
+    #[test]
+    fn locations_should_share_upstream_group_unless_changed() {
+        let path = std::env::temp_dir().join("storm_upstream_location_test.conf");
+        std::fs::write(&path, "load_balancer.servers = 127.0.0.1:8080
+            load_balancer.keepalive = 4
+            [location /retries]
+            load_balancer.retries = 0
+            [location /other]
+            load_balancer.servers = 127.0.0.1:8081
+").unwrap();
+        let conf = Conf::new(args(&["", "-f", path.to_str().unwrap()])).unwrap();
+        let group = conf.upstream_group.as_ref().unwrap();
+
+        assert_eq!(group.keepalive, 4);
+        assert!(Arc::ptr_eq(group, conf.locations[0].conf.upstream_group.as_ref().unwrap()));
+        let other = conf.locations[1].conf.upstream_group.as_ref().unwrap();
+        assert!(!Arc::ptr_eq(group, other));
+        assert_eq!(other.upstreams[0].server.addr, "127.0.0.1:8081".parse::<SocketAddr>().unwrap());
+        assert_eq!(other.keepalive, 4);
+    }
+
+    #[test]
+    fn idle_pool_should_reuse_recent_connections() {
+        let pool = IdlePool::new();
+        let now = Instant::now();
+        let timeout = Duration::from_secs(60);
+        pool.put(1, now, 2);
+        pool.put(2, now, 2);
+        pool.put(3, now + Duration::from_secs(30), 2);
+        pool.put(4, now, 0);
+
+        assert_eq!(pool.take(now + Duration::from_secs(31), timeout), Some(3));
+        assert_eq!(pool.take(now + Duration::from_secs(61), timeout), None);
+    }
+
+    #[test]
+    fn servers_should_accept_host_names_and_ipv6() {
+        let path = std::env::temp_dir().join("storm_upstream_dns_test.conf");
+        std::fs::write(&path, "load_balancer.servers = [::1]:8080 weight=2\n\
+            load_balancer.servers = LOCALHOST:8081\n").unwrap();
+        let conf = Conf::new(args(&["", "-f", path.to_str().unwrap()])).unwrap();
+        let servers = &conf.load_balancing_servers;
+
+        assert_eq!(servers[0].addr, "[::1]:8080".parse::<SocketAddr>().unwrap());
+        assert_eq!(servers[0].weight, 2);
+        assert!(servers[1..].iter().all(|s| s.host.as_deref() == Some("localhost") && s.addr.port() == 8081));
+        assert!(servers[1..].iter().all(|s| s.addr.ip().is_loopback()));
+
+        std::fs::write(&path, "load_balancer.servers = ::1:8080\n").unwrap();
+        assert!(Conf::new(args(&["", "-f", path.to_str().unwrap()])).is_err());
+    }
+
+    #[test]
+    fn resolved_addresses_should_be_spread_over_servers() {
+        let server = UpstreamServer { host: Some("app.internal".to_string()), ..UpstreamServer::new("10.0.0.1:80".parse().unwrap()) };
+        let servers = vec![
+            server.clone(),
+            UpstreamServer { addr: "10.0.0.2:80".parse().unwrap(), ..server },
+            UpstreamServer::new("10.0.0.9:80".parse().unwrap()),
+        ];
+        let group = UpstreamGroup::new(&servers, BalanceStrategy::RoundRobin, HashKey::Ip);
+        let addrs: Vec<SocketAddr> = vec!["10.0.0.2:80".parse().unwrap(), "10.0.0.3:80".parse().unwrap()];
+
+        assert_eq!(group.hosts(), vec![("app.internal".to_string(), 80)]);
+        assert_eq!(group.update_addrs("app.internal", 80, &addrs), vec![0]);
+        assert_eq!(group.upstreams[0].addr(), addrs[1]);
+        assert_eq!(group.upstreams[1].addr(), addrs[0]);
+        assert_eq!(group.update_addrs("app.internal", 80, &addrs[..1]), vec![0]);
+        assert_eq!(group.upstreams[0].addr(), addrs[0]);
+        assert_eq!(group.upstreams[2].addr(), "10.0.0.9:80".parse().unwrap());
+    }
+}
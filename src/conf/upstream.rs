@@ -0,0 +1,478 @@
+use crate::conf::upstream_tls::TlsClient;
+use crate::server::upstream_socket::UpstreamSocket;
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Points on the hash ring for each unit of server weight.
+const RING_POINTS: u32 = 64;
+
+/// Interval at which upstream host names are resolved again.
+pub const DEFAULT_DNS_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpstreamServer {
+    pub addr: SocketAddr,
+    /// Host name the address was resolved from.
+    pub host: Option<String>,
+    /// Connect with TLS, set by the `https://` prefix.
+    pub tls: bool,
+    pub weight: u32,
+    /// Failures within `fail_timeout` after which the server is not used for `fail_timeout`, `0` disables it.
+    pub max_fails: u32,
+    pub fail_timeout: Duration,
+}
+
+impl UpstreamServer {
+    pub fn new(addr: SocketAddr) -> UpstreamServer {
+        UpstreamServer { addr, host: None, tls: false, weight: 1, max_fails: 1, fail_timeout: Duration::from_secs(10) }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BalanceStrategy {
+    RoundRobin,
+    Weighted,
+    LeastConnections,
+    RandomTwo,
+    Hash,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HashKey {
+    Ip,
+    Header(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HealthCheckKind {
+    Tcp,
+    Http,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthCheck {
+    /// Checks are disabled without a kind.
+    pub kind: Option<HealthCheckKind>,
+    pub path: String,
+    /// Expected statuses like `200`, `2xx` or `200,301`.
+    pub status: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Successful checks before an unhealthy server is used again.
+    pub rise: u32,
+    /// Failed checks before a healthy server is removed.
+    pub fall: u32,
+}
+
+impl Default for HealthCheck {
+    fn default() -> HealthCheck {
+        HealthCheck {
+            kind: None,
+            path: "/".to_string(),
+            status: "2xx,3xx".to_string(),
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(2),
+            rise: 2,
+            fall: 3,
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn parse_kind(value: &str) -> Result<Option<HealthCheckKind>, Box<dyn Error>> {
+        match value.trim() {
+            "off" => Ok(None),
+            "tcp" => Ok(Some(HealthCheckKind::Tcp)),
+            "http" => Ok(Some(HealthCheckKind::Http)),
+            _ => Err(format!("Unknown health check {}", value))?
+        }
+    }
+
+    pub fn status_matches(&self, status: u32) -> bool {
+        let status = status.to_string();
+        self.status.split(',').map(|s| s.trim()).any(|pattern| {
+            pattern.len() == 3 && pattern.chars().zip(status.chars()).all(|(p, c)| p == 'x' || p == c)
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+impl BalanceStrategy {
+    pub fn parse(value: &str) -> Result<BalanceStrategy, Box<dyn Error>> {
+        match value.trim() {
+            "round_robin" => Ok(BalanceStrategy::RoundRobin),
+            "weighted" => Ok(BalanceStrategy::Weighted),
+            "least_conn" => Ok(BalanceStrategy::LeastConnections),
+            "random_two" => Ok(BalanceStrategy::RandomTwo),
+            "hash" => Ok(BalanceStrategy::Hash),
+            _ => Err(format!("Unknown load balancing strategy {}", value))?
+        }
+    }
+}
+
+impl HashKey {
+    pub fn parse(value: &str) -> Result<HashKey, Box<dyn Error>> {
+        match value.trim() {
+            "ip" => Ok(HashKey::Ip),
+            value => match value.strip_prefix("header:") {
+                Some(name) if !name.trim().is_empty() => Ok(HashKey::Header(name.trim().to_string())),
+                _ => Err(format!("Invalid load balancer hash key {}", value))?
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct FailState {
+    fails: u32,
+    since: Option<Instant>,
+    ejected_until: Option<Instant>,
+    /// After an ejection the next failure ejects the server again.
+    half_open: bool,
+}
+
+pub struct Upstream {
+    pub server: UpstreamServer,
+    /// Current address, changed when the host name resolves differently.
+    addr: Mutex<SocketAddr>,
+    active: AtomicUsize,
+    healthy: AtomicBool,
+    /// Consecutive health check results contradicting the current state.
+    checks: AtomicUsize,
+    fail_state: Mutex<FailState>,
+    /// Keep-alive connections waiting for the next request.
+    pub idle: IdlePool<UpstreamSocket>,
+}
+
+/// Idle connections, the most recently used last.
+pub struct IdlePool<T> {
+    connections: Mutex<Vec<(T, Instant)>>,
+}
+
+impl<T> IdlePool<T> {
+    pub fn new() -> IdlePool<T> {
+        IdlePool { connections: Mutex::new(Vec::new()) }
+    }
+
+    /// Takes the most recently used connection, dropping the ones idle for longer than `timeout`.
+    pub fn take(&self, now: Instant, timeout: Duration) -> Option<T> {
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|(_, since)| now.duration_since(*since) < timeout);
+        connections.pop().map(|(connection, _)| connection)
+    }
+
+    pub fn clear(&self) {
+        self.connections.lock().unwrap().clear();
+    }
+
+    /// Keeps the connection for reuse, the oldest one is closed when there are more than `size`.
+    pub fn put(&self, connection: T, now: Instant, size: usize) {
+        if size == 0 {
+            return;
+        }
+        let mut connections = self.connections.lock().unwrap();
+        connections.push((connection, now));
+        if connections.len() > size {
+            connections.remove(0);
+        }
+    }
+}
+
+impl<T> Default for IdlePool<T> {
+    fn default() -> IdlePool<T> {
+        IdlePool::new()
+    }
+}
+
+impl Upstream {
+    pub fn addr(&self) -> SocketAddr {
+        *self.addr.lock().unwrap()
+    }
+
+    /// Name for logs, the host name with the current address.
+    pub fn name(&self) -> String {
+        match &self.server.host {
+            Some(host) => format!("{} ({})", host, self.addr()),
+            None => self.addr().to_string()
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn is_ejected(&self, now: Instant) -> bool {
+        self.fail_state.lock().unwrap().ejected_until.is_some_and(|until| now < until)
+    }
+
+    /// Seconds until an ejected server is used again.
+    pub fn ejected_for(&self, now: Instant) -> Option<u64> {
+        let until = self.fail_state.lock().unwrap().ejected_until?;
+        match until > now {
+            true => Some(until.duration_since(now).as_secs_f64().ceil() as u64),
+            false => None
+        }
+    }
+
+    /// Counts a failed request, returns `true` when the server was ejected.
+    pub fn record_failure(&self, now: Instant) -> bool {
+        if self.server.max_fails == 0 {
+            return false;
+        }
+        let mut state = self.fail_state.lock().unwrap();
+        if state.since.is_none_or(|since| now.duration_since(since) > self.server.fail_timeout) {
+            state.since = Some(now);
+            state.fails = 0;
+        }
+        state.fails += 1;
+        if state.fails < self.server.max_fails && !state.half_open {
+            return false;
+        }
+        state.fails = 0;
+        state.since = None;
+        state.half_open = true;
+        state.ejected_until = Some(now + self.server.fail_timeout);
+        true
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.fail_state.lock().unwrap();
+        if state.fails > 0 || state.half_open {
+            *state = FailState::default();
+        }
+    }
+
+    /// Records a health check result, returns the new state when it changed.
+    pub fn record_check(&self, success: bool, check: &HealthCheck) -> Option<bool> {
+        if success == self.is_healthy() {
+            self.checks.store(0, Ordering::Relaxed);
+            return None;
+        }
+        let count = self.checks.fetch_add(1, Ordering::Relaxed) + 1;
+        let threshold = if success { check.rise } else { check.fall };
+        if count < threshold.max(1) as usize {
+            return None;
+        }
+        self.checks.store(0, Ordering::Relaxed);
+        self.healthy.store(success, Ordering::Relaxed);
+        Some(success)
+    }
+}
+
+/// Upstream servers of a virtual host with state shared by all connections.
+pub struct UpstreamGroup {
+    pub upstreams: Vec<Upstream>,
+    pub strategy: BalanceStrategy,
+    pub hash_key: HashKey,
+    pub health_check: Option<HealthCheck>,
+    pub tls: Option<TlsClient>,
+    /// Idle connections kept per upstream, `0` closes connections after each request.
+    pub keepalive: usize,
+    pub keepalive_timeout: Duration,
+    pub dns_ttl: Duration,
+    /// PROXY protocol header version sent to the servers, health checks send one without addresses.
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    next: AtomicUsize,
+    weights: Mutex<Vec<i64>>,
+    ring: Vec<(u64, usize)>,
+}
+
+/// Counts the request as active on the upstream until dropped.
+pub struct UpstreamGuard {
+    group: Arc<UpstreamGroup>,
+    pub index: usize,
+}
+
+impl Drop for UpstreamGuard {
+    fn drop(&mut self) {
+        self.group.upstreams[self.index].active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl UpstreamGroup {
+    pub fn new(servers: &[UpstreamServer], strategy: BalanceStrategy, hash_key: HashKey) -> UpstreamGroup {
+        let mut ring = Vec::new();
+        for (idx, server) in servers.iter().enumerate() {
+            for point in 0..server.weight * RING_POINTS {
+                ring.push((Self::hash(&format!("{}#{}", server.addr, point)), idx));
+            }
+        }
+        ring.sort();
+        UpstreamGroup {
+            upstreams: servers.iter().map(|s| Upstream {
+                server: s.clone(),
+                addr: Mutex::new(s.addr),
+                active: AtomicUsize::new(0),
+                healthy: AtomicBool::new(true),
+                checks: AtomicUsize::new(0),
+                fail_state: Mutex::new(FailState::default()),
+                idle: IdlePool::new(),
+            }).collect(),
+            strategy,
+            hash_key,
+            health_check: None,
+            tls: None,
+            keepalive: 0,
+            keepalive_timeout: Duration::from_secs(60),
+            dns_ttl: DEFAULT_DNS_TTL,
+            proxy_protocol: None,
+            next: AtomicUsize::new(0),
+            weights: Mutex::new(vec![0; servers.len()]),
+            ring,
+        }
+    }
+
+    fn hash(value: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn is_available(&self, index: usize, now: Instant) -> bool {
+        self.upstreams[index].is_healthy() && !self.upstreams[index].is_ejected(now)
+    }
+
+    /// Host names with ports to resolve again.
+    pub fn hosts(&self) -> Vec<(String, u16)> {
+        let mut hosts: Vec<(String, u16)> = Vec::new();
+        for server in self.upstreams.iter().map(|u| &u.server) {
+            if let Some(host) = &server.host && !hosts.contains(&(host.clone(), server.addr.port())) {
+                hosts.push((host.clone(), server.addr.port()));
+            }
+        }
+        hosts
+    }
+
+    /// Spreads new addresses of a host over its upstreams, the ones with an address still
+    /// returned keep it. The upstreams created at start are kept: extra addresses are not used and
+    /// when fewer addresses are returned some upstreams share one. Returns the changed upstreams.
+    pub fn update_addrs(&self, host: &str, port: u16, addrs: &[SocketAddr]) -> Vec<usize> {
+        if addrs.is_empty() {
+            return Vec::new();
+        }
+        let slots: Vec<usize> = (0..self.upstreams.len())
+            .filter(|idx| {
+                let server = &self.upstreams[*idx].server;
+                server.host.as_deref() == Some(host) && server.addr.port() == port
+            })
+            .collect();
+        let mut kept: Vec<SocketAddr> = Vec::new();
+        let mut moved: Vec<usize> = Vec::new();
+        for idx in slots.iter() {
+            let addr = self.upstreams[*idx].addr();
+            match addrs.contains(&addr) && !kept.contains(&addr) {
+                true => kept.push(addr),
+                false => moved.push(*idx)
+            }
+        }
+        let mut unused = addrs.iter().filter(|addr| !kept.contains(addr)).chain(addrs.iter().cycle());
+        let mut changed: Vec<usize> = Vec::new();
+        for idx in moved {
+            let upstream = &self.upstreams[idx];
+            let addr = *unused.next().unwrap();
+            let mut current = upstream.addr.lock().unwrap();
+            if *current != addr {
+                *current = addr;
+                upstream.idle.clear();
+                changed.push(idx);
+            }
+        }
+        changed
+    }
+
+    /// Picks an available upstream which is not excluded, `key` is the client address or header value for
+    /// the `hash` strategy.
+    pub fn select(self: &Arc<Self>, key: &str, excluded: &[usize]) -> Option<UpstreamGuard> {
+        let now = Instant::now();
+        let available: Vec<usize> = (0..self.upstreams.len())
+            .filter(|idx| !excluded.contains(idx) && self.is_available(*idx, now))
+            .collect();
+        if available.is_empty() {
+            return None;
+        }
+        let index = match self.strategy {
+            BalanceStrategy::RoundRobin => available[self.next.fetch_add(1, Ordering::Relaxed) % available.len()],
+            BalanceStrategy::Weighted => self.select_weighted(&available),
+            BalanceStrategy::LeastConnections => self.select_least_connections(&available),
+            BalanceStrategy::RandomTwo => self.select_random_two(&available),
+            BalanceStrategy::Hash => self.select_hash(key, &available),
+        };
+        self.upstreams[index].active.fetch_add(1, Ordering::Relaxed);
+        Some(UpstreamGuard { group: self.clone(), index })
+    }
+
+    /// Seconds until the first ejected server is used again.
+    pub fn retry_after(&self) -> u64 {
+        let now = Instant::now();
+        self.upstreams.iter().filter_map(|u| u.ejected_for(now)).min().unwrap_or(1).max(1)
+    }
+
+    /// Smooth weighted round-robin, heavier servers are picked more often but not in a row.
+    fn select_weighted(&self, available: &[usize]) -> usize {
+        let mut weights = self.weights.lock().unwrap();
+        let total: i64 = available.iter().map(|idx| self.upstreams[*idx].server.weight as i64).sum();
+        let mut best = available[0];
+        for idx in available.iter().copied() {
+            weights[idx] += self.upstreams[idx].server.weight as i64;
+            if weights[idx] > weights[best] {
+                best = idx;
+            }
+        }
+        weights[best] -= total;
+        best
+    }
+
+    /// Fewer active requests per unit of weight wins, ties are rotated.
+    fn select_least_connections(&self, available: &[usize]) -> usize {
+        let len = available.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..len)
+            .map(|offset| available[(start + offset) % len])
+            .min_by(|&a, &b| self.load_cmp(a, b))
+            .unwrap_or(available[0])
+    }
+
+    fn select_random_two(&self, available: &[usize]) -> usize {
+        let len = available.len();
+        if len == 1 {
+            return available[0];
+        }
+        let a = rand::random_range(0..len);
+        let b = (a + rand::random_range(1..len)) % len;
+        match self.load_cmp(available[a], available[b]) {
+            std::cmp::Ordering::Greater => available[b],
+            _ => available[a]
+        }
+    }
+
+    fn load_cmp(&self, a: usize, b: usize) -> std::cmp::Ordering {
+        let (a, b) = (&self.upstreams[a], &self.upstreams[b]);
+        (a.active() * b.server.weight as usize).cmp(&(b.active() * a.server.weight as usize))
+    }
+
+    /// First available server on the ring after the key hash.
+    fn select_hash(&self, key: &str, available: &[usize]) -> usize {
+        let hash = Self::hash(key);
+        let point = self.ring.partition_point(|(h, _)| *h < hash);
+        self.ring[point..]
+            .iter()
+            .chain(self.ring[..point].iter())
+            .map(|(_, idx)| *idx)
+            .find(|idx| available.contains(idx))
+            .unwrap_or(available[0])
+    }
+}
@@ -0,0 +1,120 @@
+use crate::conf::upstream::UpstreamServer;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::aws_lc_rs::default_provider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// TLS settings for `https://` load balancer servers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpstreamTls {
+    /// PEM bundle with trusted CAs, the bundled web roots are used without it.
+    pub ca: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    /// Name sent in SNI and checked in the certificate instead of the server host name or address.
+    pub server_name: Option<String>,
+    pub verify: bool,
+}
+
+impl Default for UpstreamTls {
+    fn default() -> UpstreamTls {
+        UpstreamTls { ca: None, cert: None, key: None, server_name: None, verify: true }
+    }
+}
+
+/// Client configuration shared by all connections to the upstream servers.
+#[derive(Debug)]
+pub struct TlsClient {
+    pub config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl TlsClient {
+    pub fn new(tls: &UpstreamTls) -> Result<TlsClient, Box<dyn Error>> {
+        let mut roots = RootCertStore::empty();
+        match &tls.ca {
+            Some(path) => {
+                for cert in Self::load_certs(path)? {
+                    roots.add(cert)?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+        }
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let mut config = match (&tls.cert, &tls.key) {
+            (Some(cert), Some(key)) => {
+                let file = File::open(key).map_err(|e| format!("Cannot open {}. {}", key, e))?;
+                let key = rustls_pemfile::private_key(&mut BufReader::new(file))?
+                    .ok_or(format!("No private key found in {}", key))?;
+                builder.with_client_auth_cert(Self::load_certs(cert)?, key)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => Err("Client certificate and key must be set together")?
+        };
+        if !tls.verify {
+            config.dangerous().set_certificate_verifier(Arc::new(NoVerification));
+        }
+        let server_name = match &tls.server_name {
+            Some(name) => Some(ServerName::try_from(name.clone()).map_err(|_| format!("Invalid TLS server name {}", name))?),
+            None => None
+        };
+        Ok(TlsClient { config: Arc::new(config), server_name })
+    }
+
+    /// Configured name, the host name of the server or its address.
+    pub fn server_name(&self, server: &UpstreamServer, addr: SocketAddr) -> ServerName<'static> {
+        let host = server.host.as_ref().and_then(|host| ServerName::try_from(host.clone()).ok());
+        match (&self.server_name, host) {
+            (Some(name), _) => name.clone(),
+            (None, Some(host)) => host,
+            (None, None) => ServerName::IpAddress(addr.ip().into())
+        }
+    }
+
+    fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+        let file = File::open(path).map_err(|e| format!("Cannot open {}. {}", path, e))?;
+        let certs = rustls_pemfile::certs(&mut BufReader::new(file)).collect::<Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            Err(format!("No certificates found in {}", path))?
+        }
+        Ok(certs)
+    }
+}
+
+/// Accepts any server certificate, for `load_balancer.tls_verify = no`.
+#[derive(Debug)]
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(&self,
+                          _end_entity: &CertificateDer<'_>,
+                          _intermediates: &[CertificateDer<'_>],
+                          _server_name: &ServerName<'_>,
+                          _ocsp_response: &[u8],
+                          _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self,
+                              _message: &[u8],
+                              _cert: &CertificateDer<'_>,
+                              _dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(&self,
+                              _message: &[u8],
+                              _cert: &CertificateDer<'_>,
+                              _dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        default_provider().signature_verification_algorithms.supported_schemes()
+    }
+}
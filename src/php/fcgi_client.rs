@@ -34,6 +34,14 @@ impl<'a> FcgiClient<'a> {
         let begin_body = [0u8, FCGI_RESPONDER as u8, 0, 0, 0, 0, 0, 0];
         self.write_record(&mut stream, FCGI_BEGIN_REQUEST, 1, &begin_body)?;
 
+        let remote_addr = match request.peer_addr() {
+            Some(addr) => addr.ip().to_string(),
+            None => "unix:".to_string()
+        };
+        let remote_port = match request.peer_addr() {
+            Some(addr) => addr.port().to_string(),
+            None => String::new()
+        };
         let  params = [
             ("GATEWAY_INTERFACE", "CGI/1.1"),
             ("SCRIPT_FILENAME", request.file_path()),
@@ -41,9 +49,9 @@ impl<'a> FcgiClient<'a> {
             ("DOCUMENT_ROOT", request.doc_root()),
             ("REQUEST_METHOD", request.method()),
             ("QUERY_STRING", request.query()),
-            ("REQUEST_URI", request.query_path()),
-            ("REMOTE_ADDR", &request.peer_addr().ip().to_string()),
-            ("REMOTE_PORT", &request.peer_addr().port().to_string()),
+            ("REQUEST_URI", request.request_uri()),
+            ("REMOTE_ADDR", &remote_addr),
+            ("REMOTE_PORT", &remote_port),
             ("SERVER_ADDR", "127.0.0.1"),
             ("SERVER_PROTOCOL", "HTTP/1.1"),
             ("SERVER_PORT", &self.server_port.to_string()),
@@ -54,6 +62,12 @@ impl<'a> FcgiClient<'a> {
             let content = self.encode_name_value(name, value);
             self.write_record(&mut stream, FCGI_PARAMS, 1, &content)?;
         }
+        if let Some(user) = request.remote_user() {
+            for (name, value) in [("REMOTE_USER", user), ("AUTH_TYPE", "Basic")] {
+                let content = self.encode_name_value(name, value);
+                self.write_record(&mut stream, FCGI_PARAMS, 1, &content)?;
+            }
+        }
         for (name, value) in request.headers() {
             let content = self.encode_name_value(format!("HTTP_{}", name).as_str(), value);
             self.write_record(&mut stream, FCGI_PARAMS, 1, &content)?;
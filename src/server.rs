@@ -1,4 +1,13 @@
 pub mod http_server;
 mod http_stream;
 mod cache;
-mod endpoint_dispatcher;
\ No newline at end of file
+mod rewrite;
+mod headers;
+mod auth;
+mod health_check;
+mod dns_resolver;
+mod forwarding;
+mod body_framing;
+mod proxy_protocol;
+pub mod upstream_socket;
+mod unit;
\ No newline at end of file
@@ -0,0 +1,31 @@
+use crate::conf::htpasswd::verify_password;
+use crate::conf::Conf;
+use crate::server::http_stream::HttpStream;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+pub struct Auth;
+
+impl Auth {
+    /// Returns the user authenticated by the `Authorization: Basic` header. Hashes are checked on the
+    /// blocking pool because bcrypt takes milliseconds.
+    pub async fn basic_user(stream: &HttpStream, conf: &Conf) -> Option<String> {
+        let value = stream.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+            .map(|(_, value)| value.trim())?;
+        let (scheme, credentials) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+        let decoded = STANDARD.decode(credentials.trim()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        let hash = conf.auth_users.get(user)?.clone();
+        let password = password.to_string();
+        match tokio::task::spawn_blocking(move || verify_password(&password, &hash)).await {
+            Ok(true) => Some(user.to_string()),
+            _ => None
+        }
+    }
+}
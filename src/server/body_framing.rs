@@ -0,0 +1,141 @@
+use std::error::Error;
+
+/// How the end of an HTTP/1.1 message body is found, RFC 9112 section 6.3.
+#[derive(Debug, PartialEq)]
+pub enum BodyFraming {
+    Empty,
+    Length(usize),
+    Chunked(ChunkedBody),
+    /// The body lasts until the connection is closed.
+    UntilClose,
+}
+
+impl BodyFraming {
+    pub fn response(request_method: &str, status: u32, headers: &[(String, String)]) -> Result<BodyFraming, Box<dyn Error>> {
+        if request_method == "HEAD" || (100..200).contains(&status) || status == 204 || status == 304 {
+            return Ok(BodyFraming::Empty);
+        }
+        let find = |name: &str| headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str());
+        if let Some(encoding) = find("transfer-encoding") {
+            return match encoding.rsplit(',').next().unwrap_or_default().trim().eq_ignore_ascii_case("chunked") {
+                true => Ok(BodyFraming::Chunked(ChunkedBody::default())),
+                false => Ok(BodyFraming::UntilClose)
+            };
+        }
+        match find("content-length") {
+            Some(length) => match length.trim().parse::<usize>() {
+                Ok(0) => Ok(BodyFraming::Empty),
+                Ok(length) => Ok(BodyFraming::Length(length)),
+                Err(_) => Err(format!("Invalid Content-Length {}", length))?
+            },
+            None => Ok(BodyFraming::UntilClose)
+        }
+    }
+
+    /// Consumes body bytes and returns how many belong to the body, the rest starts the next message.
+    pub fn consume(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>> {
+        match self {
+            BodyFraming::Empty => Ok(0),
+            BodyFraming::Length(remaining) => {
+                let size = data.len().min(*remaining);
+                *remaining -= size;
+                Ok(size)
+            }
+            BodyFraming::Chunked(chunked) => chunked.consume(data),
+            BodyFraming::UntilClose => Ok(data.len())
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        match self {
+            BodyFraming::Empty => true,
+            BodyFraming::Length(remaining) => *remaining == 0,
+            BodyFraming::Chunked(chunked) => chunked.is_complete(),
+            BodyFraming::UntilClose => false
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+enum ChunkState {
+    #[default]
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+/// Finds the end of a chunked body, passed through unchanged or decoded.
+#[derive(Debug, Default, PartialEq)]
+pub struct ChunkedBody {
+    state: ChunkState,
+    line: Vec<u8>,
+}
+
+impl ChunkedBody {
+    pub fn is_complete(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+
+    /// Appends the chunk data to `out`, returns how many bytes belong to the body.
+    pub fn decode(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<usize, Box<dyn Error>> {
+        self.process(data, Some(out))
+    }
+
+    fn consume(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>> {
+        self.process(data, None)
+    }
+
+    fn process(&mut self, data: &[u8], mut out: Option<&mut Vec<u8>>) -> Result<usize, Box<dyn Error>> {
+        let mut pos = 0;
+        while pos < data.len() && self.state != ChunkState::Done {
+            match self.state {
+                ChunkState::Data(remaining) => {
+                    let size = (data.len() - pos).min(remaining);
+                    if let Some(out) = out.as_mut() {
+                        out.extend_from_slice(&data[pos..pos + size]);
+                    }
+                    pos += size;
+                    self.state = match remaining - size {
+                        0 => ChunkState::DataEnd,
+                        remaining => ChunkState::Data(remaining)
+                    };
+                }
+                _ => {
+                    self.line.push(data[pos]);
+                    pos += 1;
+                    if self.line.ends_with(b"\r\n") {
+                        self.end_line()?;
+                    } else if self.line.len() > 4 * 1024 {
+                        return Err("Chunk line too long")?;
+                    }
+                }
+            }
+        }
+        Ok(pos)
+    }
+
+    fn end_line(&mut self) -> Result<(), Box<dyn Error>> {
+        let line = String::from_utf8_lossy(&self.line[..self.line.len() - 2]).to_string();
+        self.line.clear();
+        self.state = match self.state {
+            ChunkState::Size => {
+                let size = line.split(';').next().unwrap_or_default().trim();
+                match usize::from_str_radix(size, 16) {
+                    Ok(0) => ChunkState::Trailer,
+                    Ok(size) => ChunkState::Data(size),
+                    Err(_) => return Err(format!("Invalid chunk size {}", size))?
+                }
+            }
+            ChunkState::DataEnd if line.is_empty() => ChunkState::Size,
+            ChunkState::DataEnd => return Err("Missing chunk end")?,
+            ChunkState::Trailer if line.is_empty() => ChunkState::Done,
+            _ => ChunkState::Trailer
+        };
+        Ok(())
+    }
+}
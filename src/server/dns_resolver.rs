@@ -0,0 +1,38 @@
+use crate::conf::upstream::UpstreamGroup;
+use crate::logger::Logger;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::lookup_host;
+
+pub struct DnsResolver;
+
+impl DnsResolver {
+    /// Resolves host names of the group every `dns_ttl` with the system resolver. Addresses are kept
+    /// when a name cannot be resolved.
+    pub async fn run(group: Arc<UpstreamGroup>, logger: Arc<Logger>) {
+        let hosts = group.hosts();
+        if hosts.is_empty() || group.dns_ttl.is_zero() {
+            return;
+        }
+        let mut interval = tokio::time::interval(group.dns_ttl);
+        // Addresses were resolved when the configuration was loaded
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            for (host, port) in hosts.iter() {
+                let mut addrs: Vec<SocketAddr> = match lookup_host((host.as_str(), *port)).await {
+                    Ok(addrs) => addrs.collect(),
+                    Err(e) => {
+                        logger.log_e(format!("Could not resolve {}. {}", host, e).as_str());
+                        continue;
+                    }
+                };
+                addrs.sort();
+                addrs.dedup();
+                for idx in group.update_addrs(host, *port, &addrs) {
+                    logger.log_i(format!("Upstream {} resolved to {}", host, group.upstreams[idx].addr()).as_str());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::conf::upstream::Upstream;
+use crate::conf::Conf;
+use crate::server::headers::Headers;
+use crate::server::http_stream::HttpStream;
+use std::net::{IpAddr, SocketAddr};
+
+/// Headers meaningful only for a single connection, RFC 9110 section 7.6.1.
+/// `Transfer-Encoding` is kept because message bodies are passed unchanged.
+const HOP_BY_HOP: [&str; 7] = [
+    "connection", "keep-alive", "proxy-connection", "proxy-authenticate", "proxy-authorization", "te", "upgrade",
+];
+
+pub struct Forwarding;
+
+impl Forwarding {
+    /// Removes hop-by-hop headers, including the ones listed in `Connection`.
+    pub fn strip_hop_by_hop(headers: &mut Vec<(String, String)>) {
+        let listed: Vec<String> = headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+            .flat_map(|(_, value)| value.split(',').map(|v| v.trim().to_lowercase()))
+            .collect();
+        headers.retain(|(name, _)| {
+            let name = name.to_lowercase();
+            !HOP_BY_HOP.contains(&name.as_str()) && !listed.contains(&name) && name != "trailer"
+        });
+    }
+
+    /// Request headers sent to an upstream.
+    pub fn request_headers(stream: &HttpStream, peer: Option<SocketAddr>, conf: &Conf) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = stream.headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        Self::strip_hop_by_hop(&mut headers);
+
+        let proto = if stream.is_tls() { "https" } else { "http" };
+        let host = Headers::find(&stream.headers, "host").map(|h| h.to_string());
+        if conf.proxy_x_forwarded {
+            let trusted = conf.is_trusted_proxy(peer);
+            let forwarded_for = match (Self::take(&mut headers, "x-forwarded-for"), peer) {
+                (Some(list), Some(peer)) => Some(format!("{}, {}", list, peer.ip())),
+                (None, Some(peer)) => Some(peer.ip().to_string()),
+                (list, None) => list
+            };
+            if let Some(forwarded_for) = forwarded_for {
+                headers.push(("X-Forwarded-For".to_string(), forwarded_for));
+            }
+            let port = host
+                .as_deref()
+                .and_then(|h| h.rsplit_once(':'))
+                .filter(|(_, port)| port.parse::<u16>().is_ok())
+                .map(|(_, port)| port.to_string())
+                .unwrap_or(if stream.is_tls() { "443" } else { "80" }.to_string());
+            let values = [
+                ("X-Forwarded-Proto", Some(proto.to_string())),
+                ("X-Forwarded-Host", host.clone()),
+                ("X-Forwarded-Port", Some(port)),
+            ];
+            for (name, value) in values {
+                let incoming = Self::take(&mut headers, name);
+                let value = if trusted { incoming.or(value) } else { value };
+                if let Some(value) = value {
+                    headers.push((name.to_string(), value));
+                }
+            }
+        }
+        if conf.proxy_forwarded {
+            let node = match peer.map(|p| p.ip()) {
+                Some(IpAddr::V6(ip)) => format!("\"[{}]\"", ip),
+                Some(ip) => ip.to_string(),
+                None => "unknown".to_string()
+            };
+            let mut element = format!("for={};proto={}", node, proto);
+            if let Some(host) = &host {
+                element.push_str(&format!(";host=\"{}\"", host.replace('"', "")));
+            }
+            Self::append(&mut headers, "Forwarded", &element);
+        }
+        if conf.proxy_via {
+            Self::append(&mut headers, "Via", "1.1 stormsrv");
+        }
+        // HTTP/1.1 connections are kept alive without the header
+        if conf.upstream_group.as_ref().is_none_or(|group| group.keepalive == 0) {
+            headers.push(("Connection".to_string(), "close".to_string()));
+        }
+        headers
+    }
+
+    /// Prepares upstream response headers for the client.
+    pub fn response_headers(headers: &mut Vec<(String, String)>, conf: &Conf) {
+        Self::strip_hop_by_hop(headers);
+        if conf.proxy_via {
+            Self::append(headers, "Via", "1.1 stormsrv");
+        }
+        headers.push(("Connection".to_string(), "close".to_string()));
+    }
+
+    /// Rewrites upstream URLs in `Location` and `Refresh`, and cookie domains and paths in `Set-Cookie`.
+    pub fn rewrite_response_headers(headers: &mut [(String, String)], stream: &HttpStream, upstream: &Upstream, conf: &Conf) {
+        let mut redirects = conf.proxy_redirects.clone();
+        if conf.proxy_redirect_default && let Some(host) = Headers::find(&stream.headers, "host") {
+            let proto = if stream.is_tls() { "https" } else { "http" };
+            for origin in Self::upstream_origins(upstream) {
+                redirects.push((origin, format!("{}://{}/", proto, host)));
+            }
+        }
+        for (name, value) in headers.iter_mut() {
+            let name = name.to_lowercase();
+            if name == "location" {
+                *value = Self::replace_prefix(value, &redirects);
+            }
+            // e.g. `5; url=http://10.0.0.5:8080/`
+            if name == "refresh" && let Some(idx) = value.to_ascii_lowercase().find("url=") {
+                let (head, url) = value.split_at(idx + 4);
+                *value = format!("{}{}", head, Self::replace_prefix(url, &redirects));
+            }
+            if name == "set-cookie" {
+                *value = Self::rewrite_cookie(value, &conf.proxy_cookie_domains, &conf.proxy_cookie_paths);
+            }
+        }
+    }
+
+    /// Replaces the start of the value with the first matching rule.
+    pub fn replace_prefix(value: &str, rules: &[(String, String)]) -> String {
+        match rules.iter().find(|(from, _)| value.starts_with(from.as_str())) {
+            Some((from, to)) => format!("{}{}", to, &value[from.len()..]),
+            None => value.to_string()
+        }
+    }
+
+    /// Rewrites the `Domain` and `Path` attributes of a `Set-Cookie` value.
+    pub fn rewrite_cookie(value: &str, domains: &[(String, String)], paths: &[(String, String)]) -> String {
+        let mut parts: Vec<String> = value.split(';').map(|p| p.trim().to_string()).collect();
+        for part in parts.iter_mut().skip(1) {
+            let (name, attribute) = match part.split_once('=') {
+                Some((name, attribute)) => (name.trim().to_lowercase(), attribute.trim().to_string()),
+                None => continue
+            };
+            if name == "domain" {
+                let domain = attribute.trim_start_matches('.');
+                let found = domains.iter().find(|(from, _)| from.trim_start_matches('.').eq_ignore_ascii_case(domain));
+                if let Some((_, to)) = found {
+                    *part = format!("Domain={}", to);
+                }
+            }
+            if name == "path" {
+                *part = format!("Path={}", Self::replace_prefix(&attribute, paths));
+            }
+        }
+        parts.join("; ")
+    }
+
+    /// URLs the upstream may use for itself, with a trailing slash to match whole hosts only.
+    fn upstream_origins(upstream: &Upstream) -> Vec<String> {
+        let scheme = if upstream.server.tls { "https" } else { "http" };
+        let addr = upstream.addr();
+        let default_port = addr.port() == if upstream.server.tls { 443 } else { 80 };
+        let mut hosts = vec![addr.to_string()];
+        if default_port {
+            hosts.push(match addr {
+                SocketAddr::V6(addr) => format!("[{}]", addr.ip()),
+                SocketAddr::V4(addr) => addr.ip().to_string()
+            });
+        }
+        if let Some(host) = &upstream.server.host {
+            hosts.push(format!("{}:{}", host, addr.port()));
+            if default_port {
+                hosts.push(host.clone());
+            }
+        }
+        hosts.into_iter().map(|host| format!("{}://{}/", scheme, host)).collect()
+    }
+
+    fn take(headers: &mut Vec<(String, String)>, name: &str) -> Option<String> {
+        let values: Vec<String> = headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+            .collect();
+        headers.retain(|(header, _)| !header.eq_ignore_ascii_case(name));
+        match values.is_empty() {
+            true => None,
+            false => Some(values.join(", "))
+        }
+    }
+
+    fn append(headers: &mut Vec<(String, String)>, name: &str, value: &str) {
+        let value = match Self::take(headers, name) {
+            Some(existing) => format!("{}, {}", existing, value),
+            None => value.to_string()
+        };
+        headers.push((name.to_string(), value));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::conf::header::HeaderAction;
+use crate::conf::Conf;
+use std::collections::HashMap;
+
+pub struct Headers;
+
+impl Headers {
+    pub fn find<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Adds CORS headers for the request origin and applies `header.*` rules of the conf to response headers.
+    pub fn apply(headers: &mut Vec<(String, String)>, status: u32, request: &HashMap<String, String>, conf: &Conf) {
+        if !conf.cors.origins.is_empty() && let Some(origin) = Self::find(request, "origin") {
+            let cors = conf.cors.response_headers(origin);
+            if !cors.is_empty() {
+                headers.retain(|(name, _)| {
+                    !name.eq_ignore_ascii_case("access-control-allow-origin") &&
+                    !name.eq_ignore_ascii_case("access-control-allow-credentials")
+                });
+                headers.extend(cors);
+            }
+        }
+        if conf.headers.is_empty() {
+            return;
+        }
+        let content_type = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        for rule in conf.headers.iter().filter(|r| r.matches(status, &content_type)) {
+            if rule.action != HeaderAction::Add {
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case(&rule.name));
+            }
+            if rule.action != HeaderAction::Remove {
+                headers.push((rule.name.clone(), rule.value.clone()));
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::conf::upstream::{HealthCheck, HealthCheckKind, ProxyProtocolVersion, Upstream, UpstreamGroup};
+use crate::conf::upstream_tls::TlsClient;
+use crate::logger::Logger;
+use crate::server::proxy_protocol::ProxyProtocol;
+use crate::server::upstream_socket::UpstreamSocket;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+pub struct HealthChecker;
+
+impl HealthChecker {
+    /// Checks all servers of the group periodically and updates their state.
+    pub async fn run(group: Arc<UpstreamGroup>, logger: Arc<Logger>) {
+        let check = match &group.health_check {
+            Some(check) => Arc::new(check.clone()),
+            None => return
+        };
+        // Servers expecting the PROXY protocol get a header without client addresses
+        let proxy_header: Option<Arc<[u8]>> = group.proxy_protocol.as_ref().map(|version| match version {
+            ProxyProtocolVersion::V1 => ProxyProtocol::encode_v1(None).into(),
+            ProxyProtocolVersion::V2 => ProxyProtocol::encode_v2(None).into()
+        });
+        let mut interval = tokio::time::interval(check.interval);
+        loop {
+            interval.tick().await;
+            let mut checks = JoinSet::new();
+            for idx in 0..group.upstreams.len() {
+                let group = group.clone();
+                let check = check.clone();
+                let proxy_header = proxy_header.clone();
+                checks.spawn(async move {
+                    let upstream = &group.upstreams[idx];
+                    (idx, Self::check(upstream, group.tls.as_ref(), proxy_header.as_deref(), &check).await)
+                });
+            }
+            while let Some(Ok((idx, success))) = checks.join_next().await {
+                let upstream = &group.upstreams[idx];
+                match upstream.record_check(success, &check) {
+                    Some(true) => logger.log_i(format!("Upstream {} is up", upstream.name()).as_str()),
+                    Some(false) => logger.log_e(format!("Upstream {} is down", upstream.name()).as_str()),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    async fn check(upstream: &Upstream,
+                   tls: Option<&TlsClient>,
+                   proxy_header: Option<&[u8]>,
+                   check: &HealthCheck) -> bool {
+        let result = timeout(check.timeout, async {
+            let mut stream = UpstreamSocket::connect(upstream, tls, proxy_header, check.timeout).await.ok()?;
+            if check.kind != Some(HealthCheckKind::Http) {
+                return Some(true);
+            }
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: stormsrv-health-check\r\nConnection: close\r\n\r\n",
+                check.path, Self::host(upstream)
+            );
+            stream.write_all(request.as_bytes()).await.ok()?;
+            let mut response = Vec::new();
+            let mut buff = [0; 256];
+            while !response.windows(2).any(|w| w == b"\r\n") && response.len() < 1024 {
+                let read_size = stream.read(&mut buff).await.ok()?;
+                if read_size == 0 {
+                    break;
+                }
+                response.extend_from_slice(&buff[..read_size]);
+            }
+            let status = String::from_utf8_lossy(&response)
+                .split_whitespace()
+                .nth(1)
+                .and_then(|s| s.parse::<u32>().ok())?;
+            Some(check.status_matches(status))
+        }).await;
+        matches!(result, Ok(Some(true)))
+    }
+
+    fn host(upstream: &Upstream) -> String {
+        match &upstream.server.host {
+            Some(host) => format!("{}:{}", host, upstream.server.addr.port()),
+            None => upstream.addr().to_string()
+        }
+    }
+}
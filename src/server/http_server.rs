@@ -1,130 +1,358 @@
+use std::collections::HashMap;
 use std::error::Error;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::io::ErrorKind;
+use std::net::SocketAddr;
 use std::path::{PathBuf};
-use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
 use tokio::sync::watch::Receiver;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
 use tokio_rustls::TlsAcceptor;
 
 use uuid::Uuid;
 use crate::conf::Conf;
+use crate::conf::access::{is_allowed, real_ip};
+use crate::conf::rate_limit::{RateLimit, RateLimitDecision, RateLimitKey};
+use crate::conf::upstream::{HashKey, ProxyProtocolVersion, Upstream, UpstreamGroup};
+use crate::conf::upstream_tls::TlsClient;
+use crate::conf::listen_addr::ListenAddr;
+use crate::conf::server_name::{best_match, normalize_host};
 use request::Request;
 use crate::logger::Logger;
-use crate::server::endpoint_dispatcher::Dispatcher;
 use crate::server::http_server::cert::build_tls_config;
 use crate::server::http_server::response::Response;
 use crate::server::http_stream::{HttpStream};
 use crate::php::Php;
+use crate::server::auth::Auth;
 use crate::server::cache::Cache;
+use crate::server::forwarding::Forwarding;
+use crate::server::body_framing::BodyFraming;
+use crate::server::headers::Headers;
+use crate::server::proxy_protocol::ProxyProtocol;
+use crate::server::upstream_socket::UpstreamSocket;
+use crate::server::dns_resolver::DnsResolver;
+use crate::server::health_check::HealthChecker;
+use crate::server::rewrite::{Rewrite, RewriteError};
 use crate::server::http_server::http_server_socket::HttpServerSocket;
+use crate::server::http_server::http_server_listener::HttpServerListener;
+use crate::server::http_server::connection_limiter::ConnectionLimiter;
 
 pub mod request;
 mod response;
 mod cert;
 pub mod http_server_socket;
+mod http_server_listener;
+mod connection_limiter;
+mod unit;
 
 
 pub struct HttpServer {
-    hosts_configuration: Arc<Vec<Conf>>,
+    hosts_configuration: Vec<Arc<Conf>>,
 }
 
 impl HttpServer {
     pub fn new(conf: Vec<Conf>) -> HttpServer {
         HttpServer {
-            hosts_configuration: Arc::new(conf)
+            hosts_configuration: conf.into_iter().map(Arc::new).collect()
         }
     }
 
-    pub async fn run(&self, server_logger: Logger,  mut rx: Receiver<bool>) -> Result<(), Box<dyn Error>> {
+    pub async fn run(&self, server_logger: Logger, rx: Receiver<bool>) -> Result<(), Box<dyn Error>> {
         if self.hosts_configuration.is_empty() {
             return Err("No hosts configuration found")?;
         }
 
-        let conf  = self.hosts_configuration.get(0).unwrap();
-        let port = conf.port;
-        let tls_enabled = conf.https_enabled;
-        for conf in self.hosts_configuration.iter() {
-            if conf.port != port {
-                return Err("All hosts must use identical port configurations")?;
-            }
-            if conf.https_enabled != tls_enabled {
-                return Err("All hosts must use identical HTTPS configurations")?;
+        let server_logger = Arc::new(server_logger);
+        let limiter = Arc::new(self.connection_limiter());
+        let mut upstream_tasks = JoinSet::new();
+        for group in self.upstream_groups() {
+            if group.health_check.is_some() {
+                upstream_tasks.spawn(HealthChecker::run(group.clone(), server_logger.clone()));
             }
+            upstream_tasks.spawn(DnsResolver::run(group, server_logger.clone()));
         }
+        let mut listeners = JoinSet::new();
+        for (address, hosts) in self.listeners() {
+            let tls_confs: Vec<Arc<Conf>> = hosts
+                .iter()
+                .filter(|(_, tls)| *tls)
+                .map(|(c, _)| c.clone())
+                .collect();
+            let plain = hosts.iter().any(|(_, tls)| !*tls);
+
+            let mut acceptor: Option<TlsAcceptor> = None;
+            if !tls_confs.is_empty() {
+                if matches!(address, ListenAddr::Unix(_)) {
+                    server_logger.log_e(format!("HTTPS is not supported on {}", address).as_str());
+                    continue;
+                }
+                acceptor = match build_tls_config(&tls_confs) {
+                    Ok(c) => Some(TlsAcceptor::from(Arc::new(c))),
+                    Err(e) => {
+                        server_logger.log_e(format!("Could not build TLS config for {}: {}", address, e).as_str());
+                        continue;
+                    }
+                };
+            }
 
-        let mut acceptor: Option<TlsAcceptor> = None;
-        if tls_enabled {
-            acceptor = match build_tls_config(&self.hosts_configuration) {
-                Ok(c) => Some(TlsAcceptor::from(Arc::new(c))),
-                Err(e) => return Err(format!("Could not build TLS config: {}", e).as_str())?
+            let socket_mode = hosts.iter().find_map(|(c, _)| c.socket_mode);
+            let listener = match HttpServerListener::bind(&address, socket_mode).await {
+                Ok(l) => l,
+                Err(e) => {
+                    server_logger.log_e(format!("Could not bind to {}: {}", address, e).as_str());
+                    continue;
+                }
+            };
+            let protocol = match (acceptor.is_some(), plain) {
+                (true, true) => "Http/Https",
+                (true, false) => "Https",
+                _ => "Http"
             };
+            server_logger.log_i(format!("{} server listening on  {}", protocol, address).as_str());
+
+            let proxy_protocol = hosts.iter().any(|(c, _)| c.proxy_protocol);
+            let timeout = hosts.iter().map(|(c, _)| c.timeout).max().unwrap_or_default();
+            let hosts = ListenerHosts { acceptor, proxy_protocol, timeout, hosts };
+            listeners.spawn(listen(listener,
+                                   Arc::new(hosts),
+                                   limiter.clone(),
+                                   server_logger.clone(),
+                                   rx.clone()));
         }
 
-        let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
-        let ip = address.ip();
-        let listener = match TcpListener::bind(address).await {
-            Ok(l) => l,
-            Err(_) => return Err(format!("Could not bind to {}", address).as_str())?
-        };
-        let protocol = if conf.https_enabled { "Https" } else { "Http" };
-        server_logger.log_i(format!("{} server listening on  {}:{}", protocol, ip, conf.port).as_str());
+        // A failing listener is skipped, the other hosts are still served
+        if listeners.is_empty() {
+            return Err("No listener could be started")?;
+        }
+        while listeners.join_next().await.is_some() { }
+        Ok(())
+    }
 
-        let server_logger = Arc::new(server_logger);
+    /// Upstream groups of enabled load balancers, locations sharing the group of their domain are listed once.
+    fn upstream_groups(&self) -> Vec<Arc<UpstreamGroup>> {
+        let mut groups: Vec<Arc<UpstreamGroup>> = Vec::new();
+        for conf in self.hosts_configuration.iter() {
+            let confs = std::iter::once(conf.as_ref()).chain(conf.locations.iter().map(|l| &l.conf));
+            for conf in confs.filter(|c| c.load_balancing_enabled) {
+                if let Some(group) = &conf.upstream_group
+                    && !groups.iter().any(|g| Arc::ptr_eq(g, group)) {
+                    groups.push(group.clone());
+                }
+            }
+        }
+        groups
+    }
+
+    /// Connection limits are shared by all hosts, the highest configured values are used.
+    fn connection_limiter(&self) -> ConnectionLimiter {
+        let confs = self.hosts_configuration.iter();
+        let workers = confs.clone().map(|c| c.workers).max().unwrap_or_default();
+        let per_ip = confs.clone().map(|c| c.max_connections_per_ip).max().unwrap_or_default();
+        let reject = confs.clone().any(|c| c.reject_overflow);
+        ConnectionLimiter::new(workers, per_ip, reject)
+    }
 
-        loop {
-            let acceptor = acceptor.clone();
-            let server_logger = server_logger.clone();
-            let confs = self.hosts_configuration.clone();
-
-            tokio::select! {
-                result = listener.accept() => {
-                    let (stream, addr) = match result {
-                        Ok(r) => r,
-                        Err(e) => {
-                            server_logger.log_e(format!("Error accepting connection: {}", e).as_str());
-                            break;
-                        }
-                    };
-                    tokio::spawn(async move {
-                        accept_request(addr, stream, tls_enabled, acceptor, confs, server_logger.clone()).await;
-                    });
+    fn listeners(&self) -> Vec<(ListenAddr, Hosts)> {
+        let mut listeners: Vec<(ListenAddr, Hosts)> = Vec::new();
+        for conf in self.hosts_configuration.iter() {
+            for (address, tls) in conf.listen_addrs() {
+                match listeners.iter_mut().find(|(a, _)| *a == address) {
+                    Some((_, hosts)) => hosts.push((conf.clone(), tls)),
+                    None => listeners.push((address, vec![(conf.clone(), tls)]))
                 }
+            }
+        }
+        listeners
+    }
+}
+
+/// Hosts served by a listener, each flagged whether it is served over TLS.
+type Hosts = Vec<(Arc<Conf>, bool)>;
 
+/// When both kinds of hosts share a listener, the protocol is detected from the first byte sent by the client.
+struct ListenerHosts {
+    acceptor: Option<TlsAcceptor>,
+    proxy_protocol: bool,
+    /// Longest `server.timeout` of the hosts, used until the host of the request is known.
+    timeout: Duration,
+    hosts: Hosts,
+}
+
+/// Size of the buffers relaying request and response bodies between the client and an upstream.
+const PROXY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Time allowed for the PROXY protocol header after a connection is accepted.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn listen(listener: HttpServerListener,
+                hosts: Arc<ListenerHosts>,
+                limiter: Arc<ConnectionLimiter>,
+                server_logger: Arc<Logger>,
+                mut rx: Receiver<bool>)
+{
+    loop {
+        let server_logger = server_logger.clone();
+        let hosts = hosts.clone();
+
+        // In queue mode the next connection is not accepted until a worker is free.
+        let permit = match limiter.reject {
+            true => None,
+            false => tokio::select! {
+                permit = limiter.acquire() => permit,
                 _ = rx.changed() => {
                     break;
                 }
             }
+        };
+
+        tokio::select! {
+            result = listener.accept() => {
+                let (stream, addr) = match result {
+                    Ok(r) => r,
+                    Err(e) => {
+                        server_logger.log_e(format!("Error accepting connection: {}", e).as_str());
+                        break;
+                    }
+                };
+                let permit = permit.or_else(|| limiter.try_acquire());
+                // Behind a PROXY protocol sender the client address is known once the header is read
+                let ip = addr.map(|a| a.ip()).filter(|_| !hosts.proxy_protocol);
+                let guard = match permit {
+                    Some(permit) => limiter.register(Some(permit), ip),
+                    None => None
+                };
+                let guard = match guard {
+                    Some(guard) => guard,
+                    None => {
+                        let client = addr.map(|a| a.ip().to_string()).unwrap_or("unix:".to_string());
+                        server_logger.log_i(format!("Connection limit reached, rejecting {}", client).as_str());
+                        tokio::spawn(reject_connection(stream, hosts));
+                        continue;
+                    }
+                };
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    accept_request(addr, stream, hosts, limiter, server_logger.clone()).await;
+                    drop(guard);
+                });
+            }
+
+            _ = rx.changed() => {
+                break;
+            }
         }
-        Ok(())
     }
 }
 
-async fn accept_request(addr: SocketAddr,
-                        stream: TcpStream,
-                        tls_enabled: bool,
-                        acceptor:Option<TlsAcceptor>,
-                        confs: Arc<Vec<Conf>>,
+/// Answers `503` on plain HTTP listeners, TLS connections are closed.
+async fn reject_connection(mut stream: HttpServerSocket, hosts: Arc<ListenerHosts>) {
+    if hosts.acceptor.is_some() {
+        return;
+    }
+    let mut response = Response::service_unavailable("The server is busy, please try again later.", 1);
+    let mut bytes = response.status_line().into_bytes();
+    for (key, value) in response.headers() {
+        bytes.extend_from_slice(format!("{}:{}\r\n", key, value).as_bytes());
+    }
+    bytes.extend_from_slice(b"\r\n");
+    let mut buff = [0; 1024];
+    while let Ok(read_size) = response.read(&mut buff) && read_size > 0 {
+        bytes.extend_from_slice(&buff[..read_size]);
+    }
+    let _ = stream.write_all(&bytes).await;
+}
+
+/// Whether the client starts a TLS handshake, `None` when nothing was received in time.
+async fn is_tls_client_hello(stream: &TcpStream, wait: Duration) -> Option<bool> {
+    let mut buf = [0u8; 1];
+    match timeout(wait, stream.peek(&mut buf)).await {
+        Ok(Ok(1)) => Some(buf[0] == 0x16),
+        Ok(_) => Some(false),
+        Err(_) => None
+    }
+}
+
+async fn accept_request(addr: Option<SocketAddr>,
+                        stream: HttpServerSocket,
+                        listener_hosts: Arc<ListenerHosts>,
+                        limiter: Arc<ConnectionLimiter>,
                         server_logger: Arc<Logger>)
 {
+    let mut stream = stream;
+    let mut addr = addr;
+    let mut local_addr = stream.local_addr();
+    if listener_hosts.proxy_protocol {
+        match timeout(PROXY_HEADER_TIMEOUT, ProxyProtocol::read(&mut stream)).await {
+            Ok(Ok(Some((source, destination)))) => {
+                addr = Some(source);
+                local_addr = Some(destination);
+            }
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => {
+                server_logger.log_e(format!("Invalid PROXY protocol header. {}", e).as_str());
+                return;
+            }
+            Err(_) => {
+                server_logger.log_e("PROXY protocol header not received");
+                return;
+            }
+        }
+    }
+    let _client_guard = match listener_hosts.proxy_protocol {
+        true => match limiter.register(None, addr.map(|a| a.ip())) {
+            Some(guard) => Some(guard),
+            None => {
+                let client = addr.map(|a| a.ip().to_string()).unwrap_or("unix:".to_string());
+                server_logger.log_i(format!("Connection limit reached, rejecting {}", client).as_str());
+                reject_connection(stream, listener_hosts).await;
+                return;
+            }
+        },
+        false => None
+    };
+
+    let tls_enabled = match (&listener_hosts.acceptor, &stream) {
+        (Some(_), HttpServerSocket::Plain(s)) if listener_hosts.hosts.iter().any(|(_, tls)| !*tls) => {
+            match is_tls_client_hello(s, listener_hosts.timeout).await {
+                Some(tls) => tls,
+                None => {
+                    server_logger.log_d("Connection closed, no data received");
+                    return;
+                }
+            }
+        }
+        (Some(_), _) => true,
+        (None, _) => false
+    };
+
     let rw_stream = if tls_enabled {
-        let acceptor = match acceptor {
+        let acceptor = match &listener_hosts.acceptor {
             Some(a) => a,
             None => { return; }
         };
-        match acceptor.accept(stream).await {
-            Ok(s) => HttpServerSocket::Tls(s),
-            Err(e) => {
+        let stream = match stream {
+            HttpServerSocket::Plain(s) => s,
+            _ => { return; }
+        };
+        match timeout(listener_hosts.timeout, acceptor.accept(stream)).await {
+            Ok(Ok(s)) => HttpServerSocket::Tls(s),
+            Ok(Err(e)) => {
                 server_logger.log_e(format!("TLS accept error: {}", e).as_str());
                 return;
             }
+            Err(_) => {
+                server_logger.log_e("TLS handshake timed out");
+                return;
+            }
         }
     }
     else {
-        HttpServerSocket::Plain(stream)
+        stream
     };
 
-    let http_stream = match HttpStream::new(rw_stream).await
+    let mut http_stream = match HttpStream::new(rw_stream, listener_hosts.timeout).await
     {
         Ok(stream) => stream,
         Err(e)  => {
@@ -133,32 +361,144 @@ async fn accept_request(addr: SocketAddr,
         }
     };
 
-    let host = http_stream.headers.iter().find(|&x| x.0.eq_ignore_ascii_case("HOST"));
-    let conf = match (host, confs.len()) {
-        (None, n) if n > 1 => {
-            server_logger.log_e("No Host header found");
+    http_stream.set_local_addr(local_addr);
+
+    let confs: Vec<&Arc<Conf>> = listener_hosts.hosts
+        .iter()
+        .filter(|(_, tls)| *tls == tls_enabled)
+        .map(|(c, _)| c)
+        .collect();
+
+    let host = http_stream.headers.iter()
+        .find(|&x| x.0.eq_ignore_ascii_case("HOST"))
+        .map(|h| normalize_host(h.1));
+    let conf = match &host {
+        Some(h) => best_match(confs.iter().flat_map(|c| c.server_names().map(move |n| (*c, n))), h),
+        None => None
+    };
+    let conf = conf
+        .or_else(|| confs.iter().find(|c| c.default_host).copied())
+        .or_else(|| if confs.len() == 1 { confs.first().copied() } else { None });
+    let conf = match conf {
+        Some(conf) => conf,
+        None => {
+            match &host {
+                Some(h) => server_logger.log_e(format!("Host {} not found", h).as_str()),
+                None => server_logger.log_e("No Host header found"),
+            }
+            let response = Response::not_found(http_stream.query_path());
+            let _ = write_response(&mut http_stream, response).await;
             return;
         }
-        (None, 1) => confs.get(0),
-        (Some(_), 1) => confs.get(0),
-        (Some(h), _) => {
-            let conf = confs.iter().find(|x| x.domain.eq(h.1));
-            if conf.is_none() {
-                server_logger.log_e(format!("Host {} not found", h.1).as_str());
-                return;
+    };
+    let logger = Logger::new(conf.logs_dir.clone());
+    let logger = Arc::new(logger);
+    http_stream.set_timeout(conf.timeout);
+
+    let real_ip_header = http_stream.headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(&conf.real_ip_header))
+        .map(|(_, value)| value.clone());
+    let peer_ip = addr.map(|a| a.ip());
+    let trusted_unix = addr.is_none() && conf.trust_unix_proxy;
+    http_stream.set_client_ip(real_ip(peer_ip, trusted_unix, real_ip_header.as_deref(), &conf.trusted_proxies));
+
+    if !tls_enabled && conf.https_enabled && conf.https_redirect_http {
+        if let Err(e) = redirect_to_https(http_stream, addr, host, conf).await {
+            logger.log_e(format!("Could not redirect to HTTPS. {}", e).as_str());
+        }
+        return;
+    }
+
+    let location = conf.location(&http_stream.decoded_path());
+    if let Some((status, target)) = location.redirect(http_stream.path(), http_stream.query()) {
+        logger.log_i(format!("Redirect {} to {}", http_stream.query_path(), target).as_str());
+        if let Err(e) = respond(http_stream, addr, Response::redirect(status, &target), location).await {
+            logger.log_e(format!("Could not redirect. {}", e).as_str());
+        }
+        return;
+    }
+
+    // Access rules of the requested location apply, as well as the ones of every location entered by rewrites
+    let mut checked: Vec<&Conf> = vec![location];
+    let mut limits: Vec<Arc<RateLimit>> = Vec::new();
+    if let Some(response) = check_access(&http_stream, location, &mut limits, &logger).await {
+        if let Err(e) = respond(http_stream, addr, response, location).await {
+            logger.log_e(format!("{}", e).as_str());
+        }
+        return;
+    }
+
+    let mut entered: Vec<&Conf> = Vec::new();
+    let result = Rewrite::apply(&mut http_stream, conf, &mut entered);
+    for location in entered {
+        if checked.iter().any(|c| std::ptr::eq(*c, location)) {
+            continue;
+        }
+        checked.push(location);
+        if let Some(response) = check_access(&http_stream, location, &mut limits, &logger).await {
+            if let Err(e) = respond(http_stream, addr, response, location).await {
+                logger.log_e(format!("{}", e).as_str());
+            }
+            return;
+        }
+    }
+    let conf = match result {
+        Ok(conf) => conf,
+        Err(error) => {
+            let (response, conf) = match error {
+                RewriteError::NotFound(conf) => (Response::not_found(http_stream.query_path()), conf),
+                RewriteError::Cycle(conf) => {
+                    logger.log_e(format!("Rewrite cycle for {}, stopped at {}", http_stream.request_uri(), http_stream.query_path()).as_str());
+                    (Response::error(500, "The request could not be processed."), conf)
+                }
+            };
+            if let Err(e) = respond(http_stream, addr, response, conf).await {
+                logger.log_e(format!("{}", e).as_str());
             }
-            conf
+            return;
         }
-        _ => return,
     };
 
-    let conf = conf.unwrap();
-    let logger = Logger::new(conf.logs_dir.clone());
-    let logger = Arc::new(logger);
+    if http_stream.method() == "OPTIONS" && !conf.cors.origins.is_empty() {
+        let origin = Headers::find(&http_stream.headers, "origin");
+        let request_method = Headers::find(&http_stream.headers, "access-control-request-method");
+        if let (Some(origin), Some(_)) = (origin, request_method) {
+            let request_headers = Headers::find(&http_stream.headers, "access-control-request-headers");
+            let mut response = Response::no_content();
+            for (name, value) in conf.cors.preflight_headers(origin, request_headers) {
+                response.set_header(&name, &value);
+            }
+            if let Err(e) = respond(http_stream, addr, response, conf).await {
+                logger.log_e(format!("{}", e).as_str());
+            }
+            return;
+        }
+    }
+
+    // Authentication required by the requested location is kept after rewrites
+    let mut authenticated: Vec<&HashMap<String, String>> = Vec::new();
+    for location in checked {
+        let realm = match &location.auth_realm {
+            Some(realm) if !authenticated.contains(&&location.auth_users) => realm,
+            _ => continue
+        };
+        match Auth::basic_user(&http_stream, location).await {
+            Some(user) => {
+                http_stream.set_remote_user(user);
+                authenticated.push(&location.auth_users);
+            }
+            None => {
+                if let Err(e) = respond(http_stream, addr, Response::unauthorized(realm), location).await {
+                    logger.log_e(format!("{}", e).as_str());
+                }
+                return;
+            }
+        }
+    }
 
     if conf.load_balancing_enabled {
-        let dispatcher = Arc::new(Mutex::new(Dispatcher::new(&conf)));
-        match dispatch_request(http_stream, dispatcher, conf).await {
+        match dispatch_request(http_stream, addr, conf, &logger).await {
             Ok(_) => server_logger.log_d("Request passed upstream successfully!"),
             Err(e) => server_logger.log_e(format!("Could not transfer stream. {}", e).as_str()),
         }
@@ -167,9 +507,106 @@ async fn accept_request(addr: SocketAddr,
     }
 }
 
+/// Checks the access rules and the rate limit of a location, returns the response refusing the request.
+/// A rate limit shared by several locations counts the request once.
+async fn check_access(http_stream: &HttpStream,
+                      conf: &Conf,
+                      limits: &mut Vec<Arc<RateLimit>>,
+                      logger: &Logger) -> Option<Response> {
+    if !is_allowed(&conf.access_rules, http_stream.client_ip()) {
+        let client = http_stream.client_ip().map(|ip| ip.to_string()).unwrap_or("unix:".to_string());
+        logger.log_i(format!("Access denied for {} to {}", client, http_stream.query_path()).as_str());
+        return Some(Response::error(403, "Access to this resource is forbidden."));
+    }
+
+    let limit = conf.rate_limit.as_ref().filter(|l| l.rate > 0.0)?;
+    if limits.iter().any(|l| Arc::ptr_eq(l, limit)) {
+        return None;
+    }
+    limits.push(limit.clone());
+    match limit.check(&rate_limit_key(http_stream, &limit.key)) {
+        RateLimitDecision::Allow => None,
+        RateLimitDecision::Delay(delay) => {
+            tokio::time::sleep(delay).await;
+            None
+        }
+        RateLimitDecision::Reject(retry_after) => {
+            logger.log_i(format!("Rate limit exceeded for {}", http_stream.query_path()).as_str());
+            Some(Response::too_many_requests(retry_after))
+        }
+    }
+}
+
+fn upstream_key(http_stream: &HttpStream, key: &HashKey) -> String {
+    let client = || match http_stream.client_ip() {
+        Some(ip) => ip.to_string(),
+        None => "unix:".to_string()
+    };
+    match key {
+        HashKey::Ip => client(),
+        HashKey::Header(name) => Headers::find(&http_stream.headers, name)
+            .map(|value| value.to_string())
+            .unwrap_or_else(client)
+    }
+}
+
+fn rate_limit_key(http_stream: &HttpStream, key: &RateLimitKey) -> String {
+    let client = || match http_stream.client_ip() {
+        Some(ip) => format!("ip:{}", ip),
+        None => "ip:unix:".to_string()
+    };
+    match key {
+        RateLimitKey::Ip => client(),
+        RateLimitKey::Vhost => String::new(),
+        RateLimitKey::Path => format!("path:{}", http_stream.path()),
+        RateLimitKey::Header(name) => http_stream.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| format!("header:{}", value))
+            .unwrap_or_else(client)
+    }
+}
+
+async fn write_response(http_stream: &mut HttpStream, mut response: Response) -> Result<(), Box<dyn Error>> {
+    http_stream.write(response.status_line().as_bytes()).await?;
+    for (key, value) in response.headers() {
+        http_stream.write(format!("{}:{}\r\n", key, value).as_bytes()).await?;
+    }
+    http_stream.write(b"\r\n").await?;
+    loop {
+        let mut buff = [0; 1024];
+        let read_size = response.read(&mut buff)?;
+        if read_size == 0 {
+            break;
+        }
+        http_stream.write(&buff[..read_size]).await?;
+    }
+    Ok(())
+}
+
+async fn respond(http_stream: HttpStream,
+                 addr: Option<SocketAddr>,
+                 response: Response,
+                 conf: &Conf) -> Result<(), Box<dyn Error>> {
+    let request = Request::new(http_stream, addr, conf)?;
+    request.output_response(response, conf).await
+}
+
+async fn redirect_to_https(http_stream: HttpStream,
+                           addr: Option<SocketAddr>,
+                           host: Option<String>,
+                           conf: &Conf) -> Result<(), Box<dyn Error>> {
+    let host = host.unwrap_or_else(|| conf.domain.to_lowercase());
+    let location = match conf.https_port() {
+        Some(port) if port != 443 => format!("https://{}:{}{}", host, port, http_stream.query_path()),
+        _ => format!("https://{}{}", host, http_stream.query_path())
+    };
+    respond(http_stream, addr, Response::redirect(conf.https_redirect_status, &location), conf).await
+}
+
 async fn handle_request(
                 http_stream: HttpStream,
-                addr: SocketAddr,
+                addr: Option<SocketAddr>,
                 logger: Arc<Logger>,
                 conf: &Conf) -> Result<(),Box<dyn Error>> {
     let mut request = match Request::new(http_stream, addr, &conf) {
@@ -180,7 +617,11 @@ async fn handle_request(
         }
     };
     let id = Uuid::new_v4();
-    logger.log_i(format!("{}| Request {} {}", id, request.method(), request.query_path()).as_str());
+    let client = match request.peer_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => "unix:".to_string()
+    };
+    logger.log_i(format!("{}| Request {} {} from {}", id, request.method(), request.query_path(), client).as_str());
 
     let req_path = request.path().to_string();
     let req_query_path = request.query_path().to_string();
@@ -237,94 +678,301 @@ async fn get_file_path_response(request: &mut Request, conf: &Conf) -> Result<Re
             return Response::php(request, php).await
         }
     }
-    Ok(Response::file(&request.file_path))
+    Ok(Response::file(&request.file_path, conf))
+}
+
+/// Stage at which passing a request to an upstream failed.
+enum UpstreamError {
+    /// Nothing was sent, the request can be passed to another upstream.
+    Connect(Box<dyn Error>),
+    /// The request was sent but the upstream did not respond.
+    NoResponse(Box<dyn Error>),
+    /// The response was partly sent to the client.
+    Response(Box<dyn Error>),
+    /// The client connection failed, the upstream is not at fault.
+    Client(Box<dyn Error>),
 }
 
+/// Passes the request to upstreams until one responds. Connection failures are retried on other upstreams,
+/// requests without a body also when the upstream did not respond. Clients get `503` when no upstream
+/// is available and `502` when all attempts failed.
 async fn dispatch_request(mut downstream: HttpStream,
-                          dispatcher: Arc<Mutex<Dispatcher>>,
-                          conf: &Conf) -> Result<(), Box<dyn Error>> {
+                          addr: Option<SocketAddr>,
+                          conf: &Conf,
+                          logger: &Logger) -> Result<(), Box<dyn Error>> {
     let ds_path = downstream.path().to_string();
     let ds_query_path = downstream.query_path().to_string();
     match Cache::try_serve_cached(&mut downstream, &ds_path, &ds_query_path, conf).await {
         Ok(res) if res => return Ok(()),
         _ => { /* continue processing */ }
     }
-    let endpoint = match dispatcher.lock().unwrap().get() {
-        Some(e) => e,
+    let group = match &conf.upstream_group {
+        Some(group) => group,
         None => return Err("No endpoint to handle request")?
     };
+    let key = upstream_key(&downstream, &group.hash_key);
+    let retry_sent = matches!(downstream.method(), "GET" | "HEAD" | "OPTIONS");
+    let mut tried: Vec<usize> = Vec::new();
+
+    while tried.len() <= conf.load_balancing_retries {
+        let guard = match group.select(&key, &tried) {
+            Some(guard) => guard,
+            None => break
+        };
+        tried.push(guard.index);
+        let upstream = &group.upstreams[guard.index];
+        let error = match proxy_request(&mut downstream, addr, group, guard.index, conf).await {
+            Ok(()) => {
+                upstream.record_success();
+                return Ok(());
+            }
+            Err(UpstreamError::Client(e)) => return Err(e),
+            Err(e) => e
+        };
+        if upstream.record_failure(Instant::now()) {
+            logger.log_e(format!("Upstream {} is unavailable for {}s", upstream.name(),
+                                 upstream.server.fail_timeout.as_secs()).as_str());
+        }
+        match error {
+            UpstreamError::Connect(e) => {
+                logger.log_e(format!("Could not connect with upstream {}. {}", upstream.name(), e).as_str());
+            }
+            UpstreamError::NoResponse(e) => {
+                logger.log_e(format!("Upstream {} did not respond. {}", upstream.name(), e).as_str());
+                if !retry_sent {
+                    break;
+                }
+            }
+            UpstreamError::Response(e) | UpstreamError::Client(e) => return Err(e)
+        }
+    }
 
-    let mut upstream = match TcpStream::connect(endpoint).await {
-        Ok(stream) => stream,
-        Err(_) => Err("Could not connect with upstream")?
+    let response = match tried.is_empty() {
+        true => Response::service_unavailable("No upstream server is available, please try again later.", group.retry_after()),
+        false => Response::error(502, "The upstream server is not responding.")
     };
+    respond(downstream, addr, response, conf).await
+}
 
-    upstream.write_all(&downstream.header_block()).await?;
-    loop {
-        let mut buff = [0; 4 * 1024];
-        let read_size = downstream.read_body(&mut buff).await?;
-        if read_size == 0 {
-            break;
-        }
-        upstream.write_all(&buff[0..read_size]).await?;
-        break;
+async fn proxy_request(downstream: &mut HttpStream,
+                       peer: Option<SocketAddr>,
+                       group: &UpstreamGroup,
+                       index: usize,
+                       conf: &Conf) -> Result<(), UpstreamError> {
+    let upstream = &group.upstreams[index];
+    // A request body cannot be sent again if a kept-alive connection turns out to be closed
+    let pooled = match downstream.body_complete() {
+        true => upstream.idle.take(Instant::now(), group.keepalive_timeout),
+        false => None
+    };
+    let (mut socket, reused) = match pooled {
+        Some(socket) => (socket, true),
+        None => (connect_upstream(downstream, peer, upstream, group.tls.as_ref(), conf).await?, false)
+    };
+    let mut result = exchange(downstream, peer, upstream, &mut socket, conf).await;
+    if reused && matches!(result, Err(UpstreamError::NoResponse(_))) {
+        drop(result);
+        socket = connect_upstream(downstream, peer, upstream, group.tls.as_ref(), conf).await?;
+        result = exchange(downstream, peer, upstream, &mut socket, conf).await;
     }
+    let reusable = result?;
+    if reusable {
+        upstream.idle.put(socket, Instant::now(), group.keepalive);
+    }
+    Ok(())
+}
 
-    let mut resp_buf: Vec<u8> = Vec::new();
-    let mut headers_parsed = false;
-    let mut cache_path: Option<PathBuf> = None;
-
-    loop {
-        let mut buff = [0; 1 * 1024];
-        let read_size = upstream.read(&mut buff).await?;
-        if read_size == 0 {
-            break;
+async fn connect_upstream(downstream: &HttpStream,
+                          peer: Option<SocketAddr>,
+                          upstream: &Upstream,
+                          tls: Option<&TlsClient>,
+                          conf: &Conf) -> Result<UpstreamSocket, UpstreamError> {
+    let proxy_header = conf.load_balancing_proxy_protocol.as_ref().map(|version| {
+        let addrs = peer.zip(downstream.local_addr());
+        match version {
+            ProxyProtocolVersion::V1 => ProxyProtocol::encode_v1(addrs),
+            ProxyProtocolVersion::V2 => ProxyProtocol::encode_v2(addrs)
         }
+    });
+    UpstreamSocket::connect(upstream, tls, proxy_header.as_deref(), conf.load_balancing_connect_timeout)
+        .await
+        .map_err(UpstreamError::Connect)
+}
 
-        if conf.cache_enabled && !headers_parsed {
-            resp_buf.extend_from_slice(&buff[..read_size]);
-            if let Some(pos) = resp_buf.windows(4).position(|w| w == b"\r\n\r\n") {
-                let header_end = pos + 4;
-                let (header_bytes, _) = resp_buf.split_at(header_end);
-                let header_str = String::from_utf8_lossy(header_bytes);
-                let mut lines = header_str.lines();
-                let first_line = lines.next().unwrap_or_default().to_string();
-                let mut headers: Vec<(String, String)> = lines
-                    .filter(|l| !l.is_empty())
-                    .filter_map(|line| {
-                        line.find(':').map(|idx| (
-                            line[..idx].to_string(),
-                            line[idx + 1..].trim().to_string(),
-                        ))
-                    })
-                    .collect();
-                cache_path = Cache::process_headers(&mut headers, conf);
-                headers_parsed = true;
-
-                let body = resp_buf[header_end..].to_vec();
-                resp_buf.clear();
-                resp_buf.extend_from_slice(first_line.as_bytes());
-                resp_buf.extend_from_slice(b"\r\n");
-                for (name, value) in headers {
-                    let header_line = format!("{}: {}\r\n", name, value);
-                    resp_buf.extend_from_slice(header_line.as_bytes());
+/// Sends the request and relays the response, returns whether the connection can be reused.
+/// The response is relayed while the request body is still sent, e.g. an early `413`.
+async fn exchange(downstream: &mut HttpStream,
+                  peer: Option<SocketAddr>,
+                  upstream: &Upstream,
+                  socket: &mut UpstreamSocket,
+                  conf: &Conf) -> Result<bool, UpstreamError> {
+    let headers = Forwarding::request_headers(downstream, peer, conf);
+    socket.write_all(&downstream.header_block(&headers)).await.map_err(|e| UpstreamError::NoResponse(e.into()))?;
+
+    let mut relay = ResponseRelay::default();
+    let mut body_sent = downstream.body_complete();
+    let mut body_buff = vec![0; PROXY_BUFFER_SIZE];
+    let mut buff = vec![0; PROXY_BUFFER_SIZE];
+    // Both reads are restarted after each transfer, so the timeout applies while neither side sends data
+    let read_timeout = conf.load_balancing_read_timeout;
+    while !relay.is_complete() {
+        tokio::select! {
+            result = timeout(read_timeout, downstream.read_body(&mut body_buff)), if !body_sent => {
+                let read_size = match result {
+                    Ok(result) => result.map_err(|e| UpstreamError::Client(e.into()))?,
+                    Err(_) => return Err(UpstreamError::Client("Request body not received in time".into()))
+                };
+                body_sent = downstream.body_complete();
+                let mut data = Vec::with_capacity(read_size + 16);
+                match downstream.is_chunked() {
+                    true if read_size > 0 => {
+                        data.extend_from_slice(format!("{:x}\r\n", read_size).as_bytes());
+                        data.extend_from_slice(&body_buff[..read_size]);
+                        data.extend_from_slice(b"\r\n");
+                    }
+                    true => {}
+                    false => data.extend_from_slice(&body_buff[..read_size])
+                }
+                if body_sent && downstream.is_chunked() {
+                    data.extend_from_slice(b"0\r\n\r\n");
+                }
+                // The upstream may stop reading after an early response
+                if let Err(e) = socket.write_all(&data).await {
+                    match relay.response_started {
+                        true => body_sent = true,
+                        false => return Err(UpstreamError::NoResponse(e.into()))
+                    }
                 }
-                resp_buf.extend_from_slice(b"\r\n");
-                resp_buf.extend_from_slice(&body);
-
-                downstream.write(&resp_buf).await?;
             }
-        } else {
-            downstream.write(&buff[0..read_size]).await?;
-            if cache_path.is_some() {
-                resp_buf.extend_from_slice(&buff[..read_size]);
+            result = timeout(read_timeout, socket.read(&mut buff)) => {
+                let result = result.unwrap_or_else(|_| Err(std::io::Error::new(ErrorKind::TimedOut, "Upstream timed out")));
+                let read_size = match result {
+                    Ok(read_size) => read_size,
+                    Err(e) if !relay.response_started => return Err(UpstreamError::NoResponse(e.into())),
+                    Err(e) => return Err(UpstreamError::Response(e.into()))
+                };
+                if read_size == 0 {
+                    break;
+                }
+                relay.relay(&buff[..read_size], downstream, upstream, conf).await?;
             }
         }
     }
-    if let Some(path) = cache_path {
-        let _ = Cache::write(&resp_buf, &path);
-    }
+    relay.finish(downstream).await?;
 
-    Ok(())
+    Ok(relay.keep_alive && relay.is_complete() && downstream.body_complete())
+}
+
+/// Response of an upstream passed to the client, parsed to find its end.
+#[derive(Default)]
+struct ResponseRelay {
+    resp_buf: Vec<u8>,
+    framing: Option<BodyFraming>,
+    cache_path: Option<PathBuf>,
+    response_started: bool,
+    keep_alive: bool,
 }
 
+impl ResponseRelay {
+    fn is_complete(&self) -> bool {
+        self.framing.as_ref().is_some_and(|f| f.is_complete())
+    }
+
+    async fn relay(&mut self,
+                   data: &[u8],
+                   downstream: &mut HttpStream,
+                   upstream: &Upstream,
+                   conf: &Conf) -> Result<(), UpstreamError> {
+        if let Some(framing) = self.framing.as_mut() {
+            let size = framing.consume(data).map_err(UpstreamError::Response)?;
+            self.keep_alive &= size == data.len();
+            downstream.write(&data[..size]).await.map_err(|e| UpstreamError::Client(e.into()))?;
+            if self.cache_path.is_some() {
+                self.resp_buf.extend_from_slice(&data[..size]);
+            }
+            return Ok(());
+        }
+
+        self.resp_buf.extend_from_slice(data);
+        while self.framing.is_none() && let Some(pos) = self.resp_buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let header_end = pos + 4;
+            let (header_bytes, _) = self.resp_buf.split_at(header_end);
+            let header_str = String::from_utf8_lossy(header_bytes);
+            let mut lines = header_str.lines();
+            let first_line = lines.next().unwrap_or_default().to_string();
+            let mut headers: Vec<(String, String)> = lines
+                .filter(|l| !l.is_empty())
+                .filter_map(|line| {
+                    line.find(':').map(|idx| (
+                        line[..idx].to_string(),
+                        line[idx + 1..].trim().to_string(),
+                    ))
+                })
+                .collect();
+            let status = first_line
+                .split_whitespace()
+                .nth(1)
+                .and_then(|s| s.parse::<u32>().ok())
+                .unwrap_or_default();
+            // Interim responses like 100 Continue are passed as they are
+            if (100..200).contains(&status) && status != 101 {
+                let interim: Vec<u8> = self.resp_buf.drain(..header_end).collect();
+                self.response_started = true;
+                downstream.write(&interim).await.map_err(|e| UpstreamError::Client(e.into()))?;
+                continue;
+            }
+            let mut framing = BodyFraming::response(downstream.method(), status, &headers)
+                .map_err(UpstreamError::Response)?;
+            self.keep_alive = first_line.starts_with("HTTP/1.1") && !headers.iter().any(|(name, value)| {
+                name.eq_ignore_ascii_case("connection") && value.to_lowercase().contains("close")
+            });
+            Forwarding::response_headers(&mut headers, conf);
+            Forwarding::rewrite_response_headers(&mut headers, downstream, upstream, conf);
+            self.cache_path = Cache::process_headers(&mut headers, conf);
+            if downstream.is_tls() && let Some(hsts) = conf.hsts_header() {
+                headers.retain(|(name, _)| !name.eq_ignore_ascii_case("strict-transport-security"));
+                headers.push(("Strict-Transport-Security".to_string(), hsts));
+            }
+            Headers::apply(&mut headers, status, &downstream.headers, conf);
+
+            let body = self.resp_buf[header_end..].to_vec();
+            let size = framing.consume(&body).map_err(UpstreamError::Response)?;
+            self.keep_alive &= size == body.len();
+            self.resp_buf.clear();
+            self.resp_buf.extend_from_slice(first_line.as_bytes());
+            self.resp_buf.extend_from_slice(b"\r\n");
+            for (name, value) in headers {
+                let header_line = format!("{}: {}\r\n", name, value);
+                self.resp_buf.extend_from_slice(header_line.as_bytes());
+            }
+            self.resp_buf.extend_from_slice(b"\r\n");
+            self.resp_buf.extend_from_slice(&body[..size]);
+
+            self.response_started = true;
+            self.framing = Some(framing);
+            downstream.write(&self.resp_buf).await.map_err(|e| UpstreamError::Client(e.into()))?;
+            if self.cache_path.is_none() {
+                self.resp_buf.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Called when the upstream closed the connection or the response is complete.
+    async fn finish(&mut self, downstream: &mut HttpStream) -> Result<(), UpstreamError> {
+        match &self.framing {
+            None if self.resp_buf.is_empty() && !self.response_started => {
+                return Err(UpstreamError::NoResponse("Upstream closed connection without response".into()));
+            }
+            None => downstream.write(&self.resp_buf).await.map_err(|e| UpstreamError::Client(e.into()))?,
+            Some(framing) if !framing.is_complete() && *framing != BodyFraming::UntilClose => {
+                return Err(UpstreamError::Response("Upstream closed connection before end of response".into()));
+            }
+            Some(_) => {}
+        }
+        if let Some(path) = &self.cache_path {
+            let _ = Cache::write(&self.resp_buf, path);
+        }
+        Ok(())
+    }
+}
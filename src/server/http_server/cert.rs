@@ -3,46 +3,88 @@ use std::{error::Error, fs::File, io::BufReader, sync::Arc};
 use rustls::crypto::aws_lc_rs::sign::any_supported_type;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use rustls::{
-    server::ResolvesServerCertUsingSni,
+    server::{ClientHello, ResolvesServerCert},
     sign::CertifiedKey,
     ServerConfig,
 };
 
+use crate::conf::server_name::{best_match, normalize_host, ServerName};
 use crate::conf::Conf;
 
+#[derive(Debug)]
+struct SniHost {
+    names: Vec<ServerName>,
+    default_host: bool,
+    key: Arc<CertifiedKey>,
+}
+
+/// Resolves certificates with the same rules as `Host` header matching:
+/// aliases, wildcards, regexes and the default host.
+#[derive(Debug)]
+struct SniResolver {
+    hosts: Vec<SniHost>,
+}
 
-fn load_certs(path: &str) -> Vec<CertificateDer<'static>> {
-    let file = File::open(path).expect("Cannot open certificate file");
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let host = client_hello.server_name().map(normalize_host);
+        let found = match &host {
+            Some(h) => best_match(self.hosts.iter().flat_map(|x| x.names.iter().map(move |n| (x, n))), h),
+            None => None
+        };
+        let found = found
+            .or_else(|| self.hosts.iter().find(|x| x.default_host))
+            .or_else(|| if self.hosts.len() == 1 { self.hosts.first() } else { None });
+        found.map(|x| x.key.clone())
+    }
+}
+
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("Cannot open certificate file {}. {}", path, e))?;
     let mut reader = BufReader::new(file);
 
-    rustls_pemfile::certs(&mut reader)
+    let certs = rustls_pemfile::certs(&mut reader)
         .collect::<Result<Vec<_>, _>>()
-        .expect("Cannot read certs")
+        .map_err(|e| format!("Cannot read certificates from {}. {}", path, e))?;
+    if certs.is_empty() {
+        Err(format!("No certificates found in {}", path))?
+    }
+    Ok(certs)
 }
 
-fn load_private_key(path: &str) -> PrivateKeyDer {
-    let file = File::open(path).expect("Cannot open private key file");
+fn load_private_key(path: &str) -> Result<PrivateKeyDer<'static>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("Cannot open private key file {}. {}", path, e))?;
     let mut reader = BufReader::new(file);
     let keys = rustls_pemfile::pkcs8_private_keys(&mut reader)
         .collect::<Result<Vec<_>, _>>()
-        .expect("Cannot read private key");
+        .map_err(|e| format!("Cannot read private key from {}. {}", path, e))?;
 
-    PrivateKeyDer::from(keys.into_iter().next().expect("No private key found"))
+    match keys.into_iter().next() {
+        Some(key) => Ok(PrivateKeyDer::from(key)),
+        None => Err(format!("No private key found in {}", path))?
+    }
 }
 
-pub fn build_tls_config(configurations: &[Conf]) -> Result<ServerConfig, Box<dyn Error>> {
-    let mut resolver = ResolvesServerCertUsingSni::new();
+pub fn build_tls_config(configurations: &[Arc<Conf>]) -> Result<ServerConfig, Box<dyn Error>> {
+    let mut resolver = SniResolver { hosts: Vec::new() };
 
     for c in configurations {
-        let cert_chain = load_certs(&c.https_pub_cert);
-        let key_der = load_private_key(&c.https_private_key);
+        let cert_chain = load_certs(&c.https_pub_cert)?;
+        let key_der = load_private_key(&c.https_private_key)?;
 
         // any_supported_type z aws-lc-rs
-        let signing_key = any_supported_type(&key_der).expect("invalid private key");
+        let signing_key = any_supported_type(&key_der)
+            .map_err(|e| format!("Invalid private key {}. {}", c.https_private_key, e))?;
         let ck = CertifiedKey::new(cert_chain, signing_key);
 
-        // add() zwraca Result<(), rustls::Error>
-        resolver.add(&c.domain, ck)?;
+        let mut names = vec![c.server_name.clone()];
+        names.extend(c.aliases.iter().cloned());
+        resolver.hosts.push(SniHost {
+            names,
+            default_host: c.default_host,
+            key: Arc::new(ck),
+        });
     }
 
     let config = ServerConfig::builder()
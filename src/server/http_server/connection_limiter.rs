@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits connections handled at once by the server and by a single client address.
+pub struct ConnectionLimiter {
+    permits: Arc<Semaphore>,
+    per_ip: usize,
+    pub reject: bool,
+    clients: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Keeps the connection slot until the connection is closed.
+pub struct ConnectionGuard {
+    _permit: Option<OwnedSemaphorePermit>,
+    limiter: Arc<ConnectionLimiter>,
+    ip: Option<IpAddr>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(max: usize, per_ip: usize, reject: bool) -> ConnectionLimiter {
+        ConnectionLimiter {
+            permits: Arc::new(Semaphore::new(max.max(1))),
+            per_ip,
+            reject,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for a free slot before the next connection is accepted.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().acquire_owned().await.ok()
+    }
+
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.permits.clone().try_acquire_owned().ok()
+    }
+
+    /// Registers the connection of a client, `None` when the client has too many connections.
+    pub fn register(self: &Arc<Self>, permit: Option<OwnedSemaphorePermit>, ip: Option<IpAddr>) -> Option<ConnectionGuard> {
+        if let Some(ip) = ip && self.per_ip > 0 {
+            let mut clients = self.clients.lock().unwrap();
+            let count = clients.entry(ip).or_insert(0);
+            if *count >= self.per_ip {
+                return None;
+            }
+            *count += 1;
+        }
+        Some(ConnectionGuard {
+            _permit: permit,
+            limiter: self.clone(),
+            ip: ip.filter(|_| self.per_ip > 0),
+        })
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(ip) = self.ip {
+            let mut clients = self.limiter.clients.lock().unwrap();
+            if let Some(count) = clients.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    clients.remove(&ip);
+                }
+            }
+        }
+    }
+}
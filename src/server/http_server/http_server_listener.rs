@@ -0,0 +1,76 @@
+use crate::conf::listen_addr::ListenAddr;
+use crate::server::http_server::http_server_socket::HttpServerSocket;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+pub enum HttpServerListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl HttpServerListener {
+    pub async fn bind(address: &ListenAddr, socket_mode: Option<u32>) -> io::Result<HttpServerListener> {
+        match address {
+            ListenAddr::Tcp(addr) => Ok(HttpServerListener::Tcp(Self::bind_tcp(*addr)?)),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                use std::fs;
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                if let Ok(meta) = fs::symlink_metadata(path) {
+                    if !meta.file_type().is_socket() {
+                        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Path exists and is not a socket"));
+                    }
+                    fs::remove_file(path)?;
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                if let Some(mode) = socket_mode {
+                    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+                }
+                Ok(HttpServerListener::Unix(listener))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => {
+                let _ = socket_mode;
+                Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not supported"))
+            }
+        }
+    }
+
+    /// IPv6 listeners accept only IPv6 clients, so `0.0.0.0` and `[::]` can share a port.
+    fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(true)?;
+        }
+        // Same as `TcpListener::bind`, a restarted server can bind while old connections are in TIME_WAIT
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+        TcpListener::from_std(socket.into())
+    }
+
+    pub async fn accept(&self) -> io::Result<(HttpServerSocket, Option<SocketAddr>)> {
+        match self {
+            HttpServerListener::Tcp(l) => {
+                let (stream, addr) = l.accept().await?;
+                Ok((HttpServerSocket::Plain(stream), Some(addr)))
+            }
+            #[cfg(unix)]
+            HttpServerListener::Unix(l) => {
+                let (stream, _) = l.accept().await?;
+                Ok((HttpServerSocket::Unix(stream), None))
+            }
+        }
+    }
+}
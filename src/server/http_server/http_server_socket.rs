@@ -1,10 +1,15 @@
+use std::net::SocketAddr;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio_rustls::server::TlsStream;
 
 pub enum HttpServerSocket {
     Plain(TcpStream),
     Tls(TlsStream<TcpStream>),
+    #[cfg(unix)]
+    Unix(UnixStream),
 }
 
 impl HttpServerSocket {
@@ -12,6 +17,26 @@ impl HttpServerSocket {
         match self {
             HttpServerSocket::Plain(s) => s.read(buf).await,
             HttpServerSocket::Tls(s) => s.read(buf).await,
+            #[cfg(unix)]
+            HttpServerSocket::Unix(s) => s.read(buf).await,
+        }
+    }
+
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> std::io::Result<()> {
+        match self {
+            HttpServerSocket::Plain(s) => s.read_exact(buf).await.map(|_| ()),
+            HttpServerSocket::Tls(s) => s.read_exact(buf).await.map(|_| ()),
+            #[cfg(unix)]
+            HttpServerSocket::Unix(s) => s.read_exact(buf).await.map(|_| ()),
+        }
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            HttpServerSocket::Plain(s) => s.local_addr().ok(),
+            HttpServerSocket::Tls(s) => s.get_ref().0.local_addr().ok(),
+            #[cfg(unix)]
+            HttpServerSocket::Unix(_) => None,
         }
     }
 
@@ -19,6 +44,8 @@ impl HttpServerSocket {
         match self {
             HttpServerSocket::Plain(s) => s.write_all(buf).await,
             HttpServerSocket::Tls(s) => s.write_all(buf).await,
+            #[cfg(unix)]
+            HttpServerSocket::Unix(s) => s.write_all(buf).await,
         }
     }
-}
\ No newline at end of file
+}
@@ -1,32 +1,24 @@
 use crate::conf::Conf;
 use crate::server::cache::Cache;
+use crate::server::headers::Headers;
 use crate::server::http_server::response::Response;
 use crate::server::http_stream::HttpStream;
 use std::collections::HashMap;
 use std::error::Error;
 use std::net::SocketAddr;
-use std::path::{Path, PathBuf};
-use urlencoding::decode;
+use std::path::PathBuf;
 
 pub struct Request {
     stream:  HttpStream,
     dir_path: String,
-    peer_addr: SocketAddr,
+    peer_addr: Option<SocketAddr>,
     pub file_path: PathBuf
 
 }
 
 impl Request {
-    pub fn new(stream: HttpStream, addr: SocketAddr, config: &Conf) ->  Result<Request, Box<dyn Error>>  {
-        let path = &stream.path();
-        let mut local_path = String::from(&config.dir);
-        if !path.contains("/..") {
-            local_path.push_str(path);
-        }
-
-        let local_path = decode(&local_path)?;
-        let local_path = local_path.as_ref();
-        let file_path = Path::new(&local_path).to_path_buf();
+    pub fn new(stream: HttpStream, addr: Option<SocketAddr>, config: &Conf) ->  Result<Request, Box<dyn Error>>  {
+        let file_path = config.file_path(stream.path())?;
 
         Ok(Request {
             stream,
@@ -41,9 +33,12 @@ impl Request {
     pub fn method(&self) -> &str { self.stream.method() }
     pub fn path(&self) -> &str { self.stream.path() }
     pub fn query_path(&self) -> &str { self.stream.query_path() }
+    pub fn request_uri(&self) -> &str { self.stream.request_uri() }
     pub fn doc_root(&self) -> &str { self.dir_path.as_str() }
-    pub fn peer_addr(&self) -> SocketAddr { self.peer_addr }
+    pub fn peer_addr(&self) -> Option<SocketAddr> { self.peer_addr }
     pub fn file_path(&self) -> &str { self.file_path.to_str().unwrap_or_default() }
+    pub fn is_tls(&self) -> bool { self.stream.is_tls() }
+    pub fn remote_user(&self) -> Option<&str> { self.stream.remote_user() }
     pub fn has_body(&self) -> bool {
         self.method() == "POST" ||
         self.method() == "PUT" ||
@@ -71,6 +66,13 @@ impl Request {
             .iter()
             .map(|(k, v)| (k.clone(), v.clone()))
             .collect();
+        if self.stream.is_tls() && let Some(hsts) = conf.hsts_header() {
+            headers.push(("Strict-Transport-Security".to_string(), hsts));
+        }
+        if conf.mime_nosniff {
+            headers.push(("X-Content-Type-Options".to_string(), "nosniff".to_string()));
+        }
+        Headers::apply(&mut headers, res.status(), &self.stream.headers, conf);
         let cache_path = Cache::process_headers(&mut headers, conf);
 
         let status_line = res.status_line();
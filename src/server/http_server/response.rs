@@ -4,6 +4,10 @@ mod dir_response;
 mod not_found_response;
 mod file_response;
 mod php_response;
+mod redirect_response;
+mod error_response;
+mod no_content_response;
+mod unit;
 
 use std::collections::HashMap;
 use std::io;
@@ -21,8 +25,57 @@ impl Response {
         &self.headers
     }
 
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
     pub fn status_line(&self) -> String {
-        format!("HTTP/1.1 {} OK\r\n", self.status)
+        format!("HTTP/1.1 {} {}\r\n", self.status, Self::reason_phrase(self.status))
+    }
+
+    pub fn reason_phrase(status: u32) -> &'static str {
+        match status {
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            421 => "Misdirected Request",
+            429 => "Too Many Requests",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            // The reason phrase is optional, RFC 9112 section 4
+            _ => ""
+        }
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -1,4 +1,5 @@
 use crate::conf::Conf;
+use crate::server::http_server::response::mime::with_charset;
 use crate::server::http_server::response::string_reader::StringReader;
 use crate::server::http_server::response::Response;
 use std::collections::HashMap;
@@ -76,7 +77,7 @@ impl Response {
 
         let mut headers = HashMap::new();
         headers.insert("Content-Length".to_string(), body.len().to_string());
-        headers.insert("Content-Type".to_string(), "text/html".to_string());
+        headers.insert("Content-Type".to_string(), with_charset("text/html".to_string(), conf));
         headers.insert("Connection".to_string(), "close".to_string());
 
         Response {
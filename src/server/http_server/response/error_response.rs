@@ -0,0 +1,44 @@
+use crate::server::http_server::response::string_reader::StringReader;
+use crate::server::http_server::response::Response;
+use std::collections::HashMap;
+
+impl Response {
+    pub fn error(status: u32, message: &str) -> Response {
+        let body = include_str!("../request_handler/templates/error.html")
+            .replace("%status%", &status.to_string())
+            .replace("%message%", message);
+
+        let mut headers = HashMap::new();
+        headers.insert("Content-Length".to_string(), body.len().to_string());
+        headers.insert("Content-Type".to_string(), "text/html".to_string());
+        headers.insert("Connection".to_string(), "close".to_string());
+
+        Response {
+            status,
+            headers,
+            content: Box::new(StringReader::new(body))
+        }
+    }
+
+    pub fn unauthorized(realm: &str) -> Response {
+        let mut response = Response::error(401, "Authentication is required to access this resource.");
+        let realm = realm.replace('\\', "\\\\").replace('"', "\\\"");
+        response.headers.insert(
+            "WWW-Authenticate".to_string(),
+            format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm)
+        );
+        response
+    }
+
+    pub fn too_many_requests(retry_after: u64) -> Response {
+        let mut response = Response::error(429, "Too many requests, please try again later.");
+        response.headers.insert("Retry-After".to_string(), retry_after.to_string());
+        response
+    }
+
+    pub fn service_unavailable(message: &str, retry_after: u64) -> Response {
+        let mut response = Response::error(503, message);
+        response.headers.insert("Retry-After".to_string(), retry_after.to_string());
+        response
+    }
+}
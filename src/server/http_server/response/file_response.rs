@@ -1,3 +1,4 @@
+use crate::conf::Conf;
 use crate::server::http_server::response::mime::get_mime;
 use crate::server::http_server::response::Response;
 use std::collections::HashMap;
@@ -7,7 +8,7 @@ use std::io::BufReader;
 use std::path::PathBuf;
 
 impl Response {
-    pub fn file(path: &PathBuf) -> Response {
+    pub fn file(path: &PathBuf, conf: &Conf) -> Response {
         let file = File::open(path).unwrap();
         let size = file.metadata().unwrap().len();
         let ext = path.extension().unwrap_or_else(|| OsStr::new(""));
@@ -16,7 +17,7 @@ impl Response {
         let mut  headers = HashMap::new();
         
         headers.insert("Content-Length".to_string(), size.to_string());
-        headers.insert("Content-Type".to_string(), get_mime(ext));
+        headers.insert("Content-Type".to_string(), get_mime(ext, conf));
         headers.insert("Connection".to_string(), "close".to_string());
 
         Response {
@@ -1,14 +1,46 @@
 
-pub fn get_mime(ext: &str) -> String {
-    let content_type = match ext {
+use crate::conf::Conf;
+
+pub fn get_mime(ext: &str, conf: &Conf) -> String {
+    let ext = ext.to_lowercase();
+    let content_type = match conf.mime_overrides.get(&ext).or(conf.mime_types.get(&ext)) {
+        Some(content_type) => content_type.clone(),
+        None => builtin_mime(&ext).to_string()
+    };
+    with_charset(content_type, conf)
+}
+
+pub fn with_charset(content_type: String, conf: &Conf) -> String {
+    let charset = match &conf.mime_charset {
+        Some(charset) => charset,
+        None => return content_type
+    };
+    if !is_text(&content_type) || content_type.contains("charset=") {
+        return content_type;
+    }
+    format!("{}; charset={}", content_type, charset)
+}
+
+fn is_text(content_type: &str) -> bool {
+    content_type.starts_with("text/") ||
+    content_type == "application/json" ||
+    content_type == "application/ld+json" ||
+    content_type == "application/javascript" ||
+    content_type == "application/xml" ||
+    content_type == "application/xhtml+xml" ||
+    content_type == "image/svg+xml"
+}
+
+fn builtin_mime(ext: &str) -> &'static str {
+    match ext {
         "aac" => "audio/aac",
-        "abw" => "application/application/x-abiword",
+        "abw" => "application/x-abiword",
         "apng" => "image/apng",
         "arc" => "application/x-freearc",
         "avif" => "image/avif",
-        "avi" => "image/x-msvideo",
+        "avi" => "video/x-msvideo",
         "azw" => "application/vnd.amazon.ebook",
-        "bz2" => "application/x-bzip",
+        "bz2" => "application/x-bzip2",
         "cda" => "application/x-cda",
         "csh" => "application/x-csh",
         "css" => "text/css",
@@ -31,11 +63,11 @@ pub fn get_mime(ext: &str) -> String {
         "jsonld" => "application/ld+json",
         "md" => "text/markdown",
         "mid" => "audio/midi",
-        "midi" => "audio/x-midi",
+        "midi" => "audio/midi",
         "mjs" => "text/javascript",
         "mp3" => "audio/mpeg",
-        "mp4" => "audio/mp4",
-        "mpeg" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "mpeg" => "video/mpeg",
         "mpkg" => "application/vnd.apple.installer+xml",
         "odp" => "application/vnd.oasis.opendocument.presentation",
         "ods" => "application/vnd.oasis.opendocument.spreadsheet",
@@ -47,8 +79,9 @@ pub fn get_mime(ext: &str) -> String {
         "otf" => "font/otf",
         "png" => "image/png",
         "pdf" => "application/pdf",
+        "ppt" => "application/vnd.ms-powerpoint",
         "php" => "application/x-php",
-        "pptx" => "application/vnd.oasis.opendocument.presentation",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
         "rar" => "application/vnd.rar",
         "rtf" => "application/rtf",
         "sh" => "application/x-sh",
@@ -56,26 +89,26 @@ pub fn get_mime(ext: &str) -> String {
         "tar" => "application/x-tar",
         "tiff" => "image/tiff",
         "tif" => "image/tiff",
-        "ts" => "vide/mp2t",
+        "ts" => "video/mp2t",
         "ttf" => "font/ttf",
         "txt" => "text/plain",
         "vsd" => "application/vnd.visio",
+        "wasm" => "application/wasm",
         "wav" => "audio/wav",
         "weba" => "audio/webm",
         "webm" => "video/webm",
-        "webp" => "video/webp",
+        "webp" => "image/webp",
         "woff" => "font/woff",
         "woff2" => "font/woff2",
         "xhtml" => "application/xhtml+xml",
         "xls" => "application/vnd.ms-excel",
-        "xlsx" => "application/vnd.oasis.opendocument.spreadsheet",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
         "xml" => "application/xml",
         "xul" => "application/vnd.mozilla.xul+xml",
         "zip" => "application/zip",
-        "3gp" => "video/3gp",
+        "3gp" => "video/3gpp",
         "3g2" => "video/3gpp2",
         "7z" => "application/x-7z-compressed",
         _ => "application/octet-stream",
-    };
-    content_type.to_string()
+    }
 }
\ No newline at end of file
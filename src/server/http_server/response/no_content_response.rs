@@ -0,0 +1,17 @@
+use crate::server::http_server::response::string_reader::StringReader;
+use crate::server::http_server::response::Response;
+use std::collections::HashMap;
+
+impl Response {
+    pub fn no_content() -> Response {
+        let mut headers = HashMap::new();
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        headers.insert("Connection".to_string(), "close".to_string());
+
+        Response {
+            status: 204,
+            headers,
+            content: Box::new(StringReader::new(String::new()))
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use crate::server::http_server::response::string_reader::StringReader;
+use crate::server::http_server::response::Response;
+use std::collections::HashMap;
+
+impl Response {
+    pub fn redirect(status: u32, location: &str) -> Response {
+        let mut headers = HashMap::new();
+        headers.insert("Location".to_string(), location.to_string());
+        headers.insert("Content-Length".to_string(), "0".to_string());
+        headers.insert("Connection".to_string(), "close".to_string());
+
+        Response {
+            status,
+            headers,
+            content: Box::new(StringReader::new(String::new()))
+        }
+    }
+}
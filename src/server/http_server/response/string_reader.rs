@@ -13,22 +13,12 @@ impl StringReader {
 
 impl Read for StringReader {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let len = buf.len();
-        let start = self.idx;
-        let end = start + len - 1;
+        let bytes = self.content.as_bytes();
+        let remaining = &bytes[self.idx.min(bytes.len())..];
+        let size = remaining.len().min(buf.len());
+        buf[..size].copy_from_slice(&remaining[..size]);
+        self.idx += size;
 
-        let mut read = 0;
-        let mut buff_idx = 0;
-        for i in start..end {
-            if i > self.content.len() - 1 {
-                break;
-            }
-            buf[buff_idx] = self.content.as_bytes()[i];
-            read += 1;
-            self.idx += 1;
-            buff_idx += 1;
-        }
-
-        Ok(read)
+        Ok(size)
     }
 }
@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod tests {
+    use crate::conf::Conf;
+    use crate::server::http_server::response::mime::get_mime;
+    use crate::server::http_server::response::Response;
+
+    fn conf() -> Conf {
+        Conf::new(vec![String::new()]).unwrap()
+    }
+
+    #[test]
+    fn get_mime_should_return_builtin_type() {
+        let conf = conf();
+
+        assert_eq!(get_mime("mp4", &conf), "video/mp4");
+        assert_eq!(get_mime("WEBP", &conf), "image/webp");
+        assert_eq!(get_mime("unknown", &conf), "application/octet-stream");
+    }
+
+    #[test]
+    fn get_mime_should_prefer_override() {
+        let mut conf = conf();
+        conf.mime_types.insert("js".to_string(), "application/javascript".to_string());
+        conf.mime_overrides.insert("js".to_string(), "text/javascript".to_string());
+
+        assert_eq!(get_mime("js", &conf), "text/javascript");
+    }
+
+    #[test]
+    fn get_mime_should_append_charset_to_text_types() {
+        let mut conf = conf();
+        conf.mime_charset = Some("utf-8".to_string());
+
+        assert_eq!(get_mime("html", &conf), "text/html; charset=utf-8");
+        assert_eq!(get_mime("json", &conf), "application/json; charset=utf-8");
+        assert_eq!(get_mime("png", &conf), "image/png");
+    }
+
+    #[test]
+    fn reason_phrase_should_not_default_to_ok() {
+        assert_eq!(Response::reason_phrase(413), "Content Too Large");
+        assert_eq!(Response::reason_phrase(410), "Gone");
+        assert_eq!(Response::reason_phrase(599), "");
+    }
+}
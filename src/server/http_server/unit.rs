@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod tests {
+    use crate::conf::listen_addr::ListenAddr;
+    use crate::server::http_server::connection_limiter::ConnectionLimiter;
+    use crate::server::http_server::http_server_listener::HttpServerListener;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    #[test]
+    fn connection_limiter_should_limit_connections_per_ip() {
+        let limiter = Arc::new(ConnectionLimiter::new(4, 2, true));
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+
+        let first = limiter.register(limiter.try_acquire(), Some(client));
+        let second = limiter.register(limiter.try_acquire(), Some(client));
+        assert!(first.is_some() && second.is_some());
+        assert!(limiter.register(limiter.try_acquire(), Some(client)).is_none());
+        assert!(limiter.register(None, Some(client)).is_none());
+        assert!(limiter.register(limiter.try_acquire(), Some(other)).is_some());
+        assert!(limiter.register(None, None).is_some());
+    }
+
+    #[test]
+    fn connection_guard_should_release_slots_when_dropped() {
+        let limiter = Arc::new(ConnectionLimiter::new(1, 1, true));
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let guard = limiter.register(limiter.try_acquire(), Some(client));
+        assert!(guard.is_some());
+        assert!(limiter.try_acquire().is_none());
+        drop(guard);
+
+        let permit = limiter.try_acquire();
+        assert!(permit.is_some());
+        assert!(limiter.register(permit, Some(client)).is_some());
+    }
+
+    #[tokio::test]
+    async fn listeners_should_bind_ipv4_and_ipv6_on_same_port() {
+        let port = std::net::TcpListener::bind("[::]:0").unwrap().local_addr().unwrap().port();
+        let v4 = ListenAddr::Tcp(format!("0.0.0.0:{}", port).parse().unwrap());
+        let v6 = ListenAddr::Tcp(format!("[::]:{}", port).parse().unwrap());
+
+        let _v4 = HttpServerListener::bind(&v4, None).await.unwrap();
+        assert!(HttpServerListener::bind(&v6, None).await.is_ok());
+    }
+}
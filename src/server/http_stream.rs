@@ -2,35 +2,58 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::io;
 use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use crate::server::body_framing::ChunkedBody;
 use crate::server::http_server::http_server_socket::HttpServerSocket;
+use urlencoding::decode;
 
 pub struct HttpStream {
     stream: HttpServerSocket,
     buffer: Vec<u8>,
     len: Option<usize>,
+    chunked: Option<ChunkedBody>,
+    /// Decoded chunk data not returned by `read_body` yet.
+    decoded: Vec<u8>,
     read: usize,
     method: String,
+    request_uri: String,
     query_path: String,
     path: String,
     query: String,
+    remote_user: Option<String>,
+    client_ip: Option<IpAddr>,
+    local_addr: Option<SocketAddr>,
+    /// Time allowed for the request header and for each read of the body.
+    timeout: Duration,
     pub headers: HashMap<String, String>
 }
 
 
 impl HttpStream {
-    pub async fn new(stream: HttpServerSocket) -> Result<HttpStream, Box<dyn Error>> {
+    pub async fn new(stream: HttpServerSocket, timeout: Duration) -> Result<HttpStream, Box<dyn Error>> {
         let mut http_reader = HttpStream {
             stream,
             buffer: Vec::with_capacity(1024),
             len: None,
+            chunked: None,
+            decoded: Vec::new(),
             read: 0,
             method: String::new(),
+            request_uri: String::new(),
+            remote_user: None,
+            client_ip: None,
+            local_addr: None,
+            timeout,
             query_path: String::new(),
             path: String::new(),
             query: String::new(),
             headers: HashMap::new()
         };
-        http_reader.init().await?;
+        match tokio::time::timeout(timeout, http_reader.init()).await {
+            Ok(result) => result?,
+            Err(_) => return Err("Request header not received in time")?
+        }
 
         Ok(http_reader)
     }
@@ -38,39 +61,114 @@ impl HttpStream {
     pub fn path(&self) -> &str { self.path.as_str() }
     pub fn query(&self) -> &str { self.query.as_str() }
     pub fn query_path(&self) -> &str {  self.query_path.as_str() }
+    /// Original request target, not changed by rewrites.
+    pub fn request_uri(&self) -> &str { self.request_uri.as_str() }
     pub fn method(&self) -> &str { self.method.as_str() }
+    pub fn decoded_path(&self) -> String {
+        match decode(&self.path) {
+            Ok(path) => path.into_owned(),
+            Err(_) => self.path.clone()
+        }
+    }
+
+    /// Internally changes path and query, e.g. `/index.php?page=1`.
+    pub fn rewrite(&mut self, query_path: &str) {
+        self.query_path = query_path.to_string();
+        match query_path.find('?') {
+            Some(index) => {
+                self.path = query_path[..index].to_string();
+                self.query = query_path[index+1..].to_string();
+            }
+            None => {
+                self.path = query_path.to_string();
+                self.query = String::new();
+            }
+        }
+    }
+
+    pub fn is_tls(&self) -> bool { matches!(self.stream, HttpServerSocket::Tls(_)) }
+    pub fn remote_user(&self) -> Option<&str> { self.remote_user.as_deref() }
+    pub fn set_remote_user(&mut self, user: String) { self.remote_user = Some(user); }
+    pub fn client_ip(&self) -> Option<IpAddr> { self.client_ip }
+    pub fn set_client_ip(&mut self, ip: Option<IpAddr>) { self.client_ip = ip; }
+    /// Address the client connected to, from the PROXY protocol header when present.
+    pub fn local_addr(&self) -> Option<SocketAddr> { self.local_addr }
+    pub fn set_local_addr(&mut self, addr: Option<SocketAddr>) { self.local_addr = addr; }
+    pub fn set_timeout(&mut self, timeout: Duration) { self.timeout = timeout; }
     pub async fn write(&mut self, buf: &[u8]) -> io::Result<()> {
         self.stream.write_all(buf).await
     }
 
-    pub async fn read_body(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
-        if self.len.is_some() {
-            let len = self.len.unwrap();
-            if self.read >= len {
+    /// Whether the whole request body was read.
+    pub fn body_complete(&self) -> bool {
+        match &self.chunked {
+            Some(chunked) => chunked.is_complete(),
+            None => self.len.is_none_or(|len| self.read >= len)
+        }
+    }
+
+    /// Whether the request body has chunked transfer coding, `read_body` returns it decoded.
+    pub fn is_chunked(&self) -> bool { self.chunked.is_some() }
+
+    pub async fn read_body(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunked.is_some() {
+            return self.read_chunked(buf).await;
+        }
+        let remaining = match self.len {
+            Some(len) if self.read < len => len - self.read,
+            _ => return Ok(0)
+        };
+        let size = buf.len().min(remaining);
+        let result = self.read_raw(&mut buf[..size]).await?;
+        if result == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Request body is incomplete"));
+        }
+        self.read += result;
+        Ok(result)
+    }
+
+    async fn read_chunked(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if !self.decoded.is_empty() {
+                let size = buf.len().min(self.decoded.len());
+                buf[..size].copy_from_slice(&self.decoded[..size]);
+                self.decoded.drain(..size);
+                self.read += size;
+                return Ok(size);
+            }
+            if self.body_complete() {
                 return Ok(0);
             }
+            let mut raw = [0; 4 * 1024];
+            let read_size = self.read_raw(&mut raw).await?;
+            if read_size == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Request body is incomplete"));
+            }
+            if let Some(chunked) = self.chunked.as_mut() {
+                chunked.decode(&raw[..read_size], &mut self.decoded)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            }
         }
-        else {
-            return Ok(0);
-        }
-        let result;
-        if self.buffer.len() > 0 {
-            let size = if self.buffer.len() > buf.len() { buf.len() } else { self.buffer.len() };
+    }
+
+    /// Reads buffered data first, then the socket.
+    async fn read_raw(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
+        if !self.buffer.is_empty() {
+            let size = buf.len().min(self.buffer.len());
             let to_copy = self.buffer.drain(..size).collect::<Vec<u8>>();
-            result = buf.write(&to_copy)?;
+            return buf.write(&to_copy);
         }
-        else {
-            result = self.stream.read(buf).await?;
+        match tokio::time::timeout(self.timeout, self.stream.read(buf)).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "Request body not received in time"))
         }
-        self.read += result;
-        Ok(result)
     }
 
-    pub fn header_block(&self) -> Vec<u8> {
+    pub fn header_block(&self, headers: &[(String, String)]) -> Vec<u8> {
         let mut header_block = Vec::new();
         let status_line = format!("{} {} HTTP/1.1\r\n", self.method, self.query_path);
         header_block.extend_from_slice(status_line.as_bytes());
-        for (name, value) in &self.headers {
+        for (name, value) in headers {
             let header_line = format!("{}: {}\r\n", name, value);
             header_block.extend_from_slice(header_line.as_bytes());
         }
@@ -133,6 +231,7 @@ impl HttpStream {
         let http_header = status_header.unwrap();
 
         self.method = http_header.0.to_uppercase();
+        self.request_uri = http_header.1.clone();
         self.query_path = http_header.1;
 
         if !methods.contains(&self.method.as_str()) {
@@ -165,7 +264,21 @@ impl HttpStream {
             self.len = Some(len)
         }
 
-        if ["POST", "PUT"].contains(&self.method.as_str()) && self.len.is_none() {
+        let encoding = self.headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding"))
+            .map(|(_, value)| value.to_lowercase());
+        if let Some(encoding) = encoding {
+            // Both headers are a request smuggling attempt, RFC 9112 section 6.1
+            if self.len.is_some() {
+                return Err("Content-Length and Transfer-Encoding both set")?;
+            }
+            if encoding.split(',').map(|e| e.trim()).ne(["chunked"]) {
+                return Err(format!("Unsupported Transfer-Encoding {}", encoding))?;
+            }
+            self.chunked = Some(ChunkedBody::default());
+        }
+
+        if ["POST", "PUT"].contains(&self.method.as_str()) && self.len.is_none() && self.chunked.is_none() {
             return Err("Content-Length required")?;
         }
 
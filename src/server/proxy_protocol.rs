@@ -0,0 +1,119 @@
+use crate::server::http_server::http_server_socket::HttpServerSocket;
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+const V1_MAX_LENGTH: usize = 107;
+
+/// Source and destination of the proxied connection, `None` for `LOCAL` and `UNKNOWN` connections.
+pub type ProxyAddrs = Option<(SocketAddr, SocketAddr)>;
+
+pub struct ProxyProtocol;
+
+impl ProxyProtocol {
+    /// Reads a PROXY protocol v1 or v2 header, leaving the data after it in the socket.
+    pub async fn read(stream: &mut HttpServerSocket) -> Result<ProxyAddrs, Box<dyn Error>> {
+        let mut header = vec![0u8; 8];
+        stream.read_exact(&mut header).await?;
+        if header.starts_with(b"PROXY ") {
+            let mut byte = [0u8; 1];
+            while !header.ends_with(b"\r\n") {
+                if header.len() >= V1_MAX_LENGTH {
+                    return Err("PROXY protocol header too long")?;
+                }
+                stream.read_exact(&mut byte).await?;
+                header.push(byte[0]);
+            }
+            return Self::parse_v1(&String::from_utf8_lossy(&header));
+        }
+        if header[..] != V2_SIGNATURE[..8] {
+            return Err("Missing PROXY protocol header")?;
+        }
+        header.resize(16, 0);
+        stream.read_exact(&mut header[8..]).await?;
+        let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+        header.resize(16 + len, 0);
+        stream.read_exact(&mut header[16..]).await?;
+        Self::parse_v2(&header)
+    }
+
+    /// Parses `PROXY TCP4 SRC DST SRC_PORT DST_PORT\r\n` or `PROXY UNKNOWN ...\r\n`.
+    pub fn parse_v1(line: &str) -> Result<ProxyAddrs, Box<dyn Error>> {
+        let parts: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+        match parts.get(1) {
+            Some(&"UNKNOWN") => return Ok(None),
+            Some(&"TCP4") | Some(&"TCP6") if parts.len() == 6 => {}
+            _ => return Err("Invalid PROXY protocol v1 header")?
+        }
+        let ip = |s: &str| s.parse::<IpAddr>().map_err(|_| "Invalid PROXY protocol address");
+        let port = |s: &str| s.parse::<u16>().map_err(|_| "Invalid PROXY protocol port");
+        let source = SocketAddr::new(ip(parts[2])?, port(parts[4])?);
+        let destination = SocketAddr::new(ip(parts[3])?, port(parts[5])?);
+        Ok(Some((source, destination)))
+    }
+
+    pub fn parse_v2(header: &[u8]) -> Result<ProxyAddrs, Box<dyn Error>> {
+        if header.len() < 16 || header[..12] != V2_SIGNATURE || header[12] >> 4 != 2 {
+            return Err("Invalid PROXY protocol v2 header")?;
+        }
+        let address = &header[16..];
+        // LOCAL command, e.g. health checks of the proxy
+        if header[12] & 0x0F == 0 {
+            return Ok(None);
+        }
+        match header[13] >> 4 {
+            1 if address.len() >= 12 => {
+                let source = Ipv4Addr::new(address[0], address[1], address[2], address[3]);
+                let destination = Ipv4Addr::new(address[4], address[5], address[6], address[7]);
+                Ok(Some((
+                    SocketAddr::new(IpAddr::V4(source), u16::from_be_bytes([address[8], address[9]])),
+                    SocketAddr::new(IpAddr::V4(destination), u16::from_be_bytes([address[10], address[11]]))
+                )))
+            }
+            2 if address.len() >= 36 => {
+                let source: [u8; 16] = address[0..16].try_into()?;
+                let destination: [u8; 16] = address[16..32].try_into()?;
+                Ok(Some((
+                    SocketAddr::new(IpAddr::V6(Ipv6Addr::from(source)), u16::from_be_bytes([address[32], address[33]])),
+                    SocketAddr::new(IpAddr::V6(Ipv6Addr::from(destination)), u16::from_be_bytes([address[34], address[35]]))
+                )))
+            }
+            // Unix or unspecified addresses
+            _ => Ok(None)
+        }
+    }
+
+    pub fn encode_v1(addrs: ProxyAddrs) -> Vec<u8> {
+        match addrs {
+            Some((source, destination)) if source.is_ipv4() == destination.is_ipv4() => {
+                let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+                format!("PROXY {} {} {} {} {}\r\n", family, source.ip(), destination.ip(), source.port(),
+                        destination.port()).into_bytes()
+            }
+            _ => b"PROXY UNKNOWN\r\n".to_vec()
+        }
+    }
+
+    pub fn encode_v2(addrs: ProxyAddrs) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        let address = match addrs {
+            Some((SocketAddr::V4(source), SocketAddr::V4(destination))) => {
+                header.extend_from_slice(&[0x21, 0x11]);
+                [&source.ip().octets()[..], &destination.ip().octets()[..],
+                    &source.port().to_be_bytes(), &destination.port().to_be_bytes()].concat()
+            }
+            Some((SocketAddr::V6(source), SocketAddr::V6(destination))) => {
+                header.extend_from_slice(&[0x21, 0x21]);
+                [&source.ip().octets()[..], &destination.ip().octets()[..],
+                    &source.port().to_be_bytes(), &destination.port().to_be_bytes()].concat()
+            }
+            _ => {
+                header.extend_from_slice(&[0x20, 0x00]);
+                Vec::new()
+            }
+        };
+        header.extend_from_slice(&(address.len() as u16).to_be_bytes());
+        header.extend_from_slice(&address);
+        header
+    }
+}
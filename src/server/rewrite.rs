@@ -0,0 +1,121 @@
+use crate::conf::rewrite::{expand, FileTest, RewriteFlag};
+use crate::conf::Conf;
+use crate::server::http_stream::HttpStream;
+
+const MAX_REWRITES: usize = 10;
+
+pub enum TryFiles {
+    /// No try_files or a file was found
+    Found,
+    /// Request was rewritten to the fallback URI
+    Fallback,
+    NotFound,
+}
+
+/// Why a request cannot be served after rewriting, with the location reached.
+pub enum RewriteError<'a> {
+    /// try_files ended with `=404`
+    NotFound(&'a Conf),
+    /// Rewrites or try_files fallbacks did not settle within `MAX_REWRITES`
+    Cycle(&'a Conf),
+}
+
+pub struct Rewrite;
+
+impl Rewrite {
+    /// Applies rewrite rules and try_files, matching locations again after `last` rewrites and
+    /// try_files fallbacks. Locations matched again are added to `entered`. Returns configuration
+    /// serving the request.
+    pub fn apply<'a>(stream: &mut HttpStream,
+                     host: &'a Conf,
+                     entered: &mut Vec<&'a Conf>) -> Result<&'a Conf, RewriteError<'a>> {
+        let mut conf = host.location(&stream.decoded_path());
+        for _ in 0..MAX_REWRITES {
+            if Self::apply_rules(stream, conf) == RewriteFlag::Last {
+                conf = host.location(&stream.decoded_path());
+                entered.push(conf);
+                continue;
+            }
+            match Self::try_files(stream, conf) {
+                TryFiles::Found => return Ok(conf),
+                TryFiles::NotFound => return Err(RewriteError::NotFound(conf)),
+                TryFiles::Fallback => {
+                    conf = host.location(&stream.decoded_path());
+                    entered.push(conf);
+                }
+            }
+        }
+        Err(RewriteError::Cycle(conf))
+    }
+
+    fn apply_rules(stream: &mut HttpStream, conf: &Conf) -> RewriteFlag {
+        for rule in conf.rewrites.iter() {
+            let path = stream.path().to_string();
+            let caps = match rule.pattern.captures(&path) {
+                Some(caps) => caps,
+                None => continue
+            };
+            if !rule.conditions.iter().all(|c| Self::test_file(conf, &path, &c.test) != c.negate) {
+                continue;
+            }
+
+            let target = expand(&rule.replacement, Some(&caps), &path, stream.query(), stream.request_uri());
+            let target = match target.split_once('?') {
+                Some((target_path, _)) if target.ends_with('?') => target_path.to_string(),
+                Some(_) if !stream.query().is_empty() => format!("{}&{}", target, stream.query()),
+                Some(_) => target,
+                None if !stream.query().is_empty() => format!("{}?{}", target, stream.query()),
+                None => target
+            };
+            stream.rewrite(&target);
+
+            if rule.flag != RewriteFlag::None {
+                return rule.flag.clone();
+            }
+        }
+        RewriteFlag::None
+    }
+
+    fn try_files(stream: &mut HttpStream, conf: &Conf) -> TryFiles {
+        let (fallback, files) = match conf.try_files.split_last() {
+            Some(f) => f,
+            None => return TryFiles::Found
+        };
+        let path = stream.path().to_string();
+        for file in files {
+            let file = expand(file, None, &path, stream.query(), stream.request_uri());
+            let found = match file.strip_suffix('/') {
+                Some(dir) => Self::test_file(conf, dir, &FileTest::Dir),
+                None => Self::test_file(conf, &file, &FileTest::File)
+            };
+            if found {
+                if file != path {
+                    let target = match stream.query() {
+                        "" => file,
+                        query => format!("{}?{}", file, query)
+                    };
+                    stream.rewrite(&target);
+                }
+                return TryFiles::Found;
+            }
+        }
+        if fallback == "=404" {
+            return TryFiles::NotFound;
+        }
+        let target = expand(fallback, None, &path, stream.query(), stream.request_uri());
+        stream.rewrite(&target);
+        TryFiles::Fallback
+    }
+
+    fn test_file(conf: &Conf, path: &str, test: &FileTest) -> bool {
+        let file_path = match conf.file_path(path) {
+            Ok(p) => p,
+            Err(_) => return false
+        };
+        match test {
+            FileTest::File => file_path.is_file(),
+            FileTest::Dir => file_path.is_dir(),
+            FileTest::Exists => file_path.exists(),
+        }
+    }
+}
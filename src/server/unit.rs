@@ -0,0 +1,215 @@
+#[cfg(test)]
+mod tests {
+    use crate::conf::access::Cidr;
+    use crate::conf::Conf;
+    use crate::server::body_framing::{BodyFraming, ChunkedBody};
+    use crate::server::forwarding::Forwarding;
+    use crate::logger::Logger;
+    use crate::server::http_server::http_server_socket::HttpServerSocket;
+    use crate::server::http_server::HttpServer;
+    use crate::server::http_stream::HttpStream;
+    use crate::server::proxy_protocol::ProxyProtocol;
+    use crate::server::rewrite::{Rewrite, RewriteError};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::watch;
+
+    async fn http_stream(request: &str) -> HttpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        client.write_all(request.as_bytes()).await.unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        HttpStream::new(HttpServerSocket::Plain(socket), Duration::from_secs(5)).await.unwrap()
+    }
+
+    /// Serves `conf` from a directory with `index.html`, the server runs until the sender is dropped.
+    async fn start_server(name: &str, conf: &str) -> (u16, watch::Sender<bool>) {
+        let dir = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "index").unwrap();
+        let path = dir.join("server.conf");
+        std::fs::write(&path, format!("server.dir = {}\n{}", dir.to_str().unwrap(), conf)).unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let args = ["", "-f", path.to_str().unwrap(), "-p", port.to_string().as_str()].map(|a| a.to_string());
+        let server = HttpServer::new(vec![Conf::new(args.to_vec()).unwrap()]);
+        let (tx, rx) = watch::channel(false);
+        tokio::spawn(async move { server.run(Logger::new(None), rx).await.unwrap() });
+        (port, tx)
+    }
+
+    async fn status(port: u16, request: &str) -> u32 {
+        let mut stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await
+            }
+        };
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        String::from_utf8_lossy(&response).split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    fn find<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn proxy_protocol_v1_should_parse_and_encode() {
+        let addrs = ProxyProtocol::parse_v1("PROXY TCP4 192.0.2.1 198.51.100.2 51234 443\r\n").unwrap().unwrap();
+        assert_eq!(addrs.0, "192.0.2.1:51234".parse::<SocketAddr>().unwrap());
+        assert_eq!(addrs.1, "198.51.100.2:443".parse::<SocketAddr>().unwrap());
+        assert!(ProxyProtocol::parse_v1("PROXY UNKNOWN\r\n").unwrap().is_none());
+        assert!(ProxyProtocol::parse_v1("PROXY TCP4 192.0.2.1 198.51.100.2 51234\r\n").is_err());
+        assert!(ProxyProtocol::parse_v1("PROXY TCP4 host 198.51.100.2 51234 443\r\n").is_err());
+
+        let encoded = ProxyProtocol::encode_v1(Some(addrs));
+        assert_eq!(encoded, b"PROXY TCP4 192.0.2.1 198.51.100.2 51234 443\r\n");
+        assert_eq!(ProxyProtocol::encode_v1(None), b"PROXY UNKNOWN\r\n");
+    }
+
+    #[test]
+    fn proxy_protocol_v2_should_parse_and_encode() {
+        let v4 = ("192.0.2.1:51234".parse().unwrap(), "198.51.100.2:443".parse().unwrap());
+        let header = ProxyProtocol::encode_v2(Some(v4));
+        assert_eq!(header.len(), 16 + 12);
+        assert_eq!(ProxyProtocol::parse_v2(&header).unwrap(), Some(v4));
+
+        let v6 = ("[2001:db8::1]:51234".parse().unwrap(), "[2001:db8::2]:443".parse().unwrap());
+        let header = ProxyProtocol::encode_v2(Some(v6));
+        assert_eq!(header.len(), 16 + 36);
+        assert_eq!(ProxyProtocol::parse_v2(&header).unwrap(), Some(v6));
+
+        let local = ProxyProtocol::encode_v2(None);
+        assert_eq!(ProxyProtocol::parse_v2(&local).unwrap(), None);
+        assert!(ProxyProtocol::parse_v2(b"GET / HTTP/1.1\r\n").is_err());
+    }
+
+    #[test]
+    fn body_framing_should_find_end_of_response() {
+        let headers = |name: &str, value: &str| vec![(name.to_string(), value.to_string())];
+
+        let mut framing = BodyFraming::response("GET", 200, &headers("Transfer-Encoding", "chunked")).unwrap();
+        assert_eq!(framing.consume(b"5;ext=1\r\nhel").unwrap(), 12);
+        assert!(!framing.is_complete());
+        assert_eq!(framing.consume(b"lo\r\n0\r\nX-Trailer: 1\r\n\r\nHTTP/1.1").unwrap(), 23);
+        assert!(framing.is_complete());
+
+        let mut framing = BodyFraming::response("GET", 200, &headers("Content-Length", "4")).unwrap();
+        assert_eq!(framing.consume(b"abcdef").unwrap(), 4);
+        assert!(framing.is_complete());
+
+        assert!(BodyFraming::response("HEAD", 200, &headers("Content-Length", "4")).unwrap().is_complete());
+        assert!(BodyFraming::response("GET", 304, &[]).unwrap().is_complete());
+        assert_eq!(BodyFraming::response("GET", 200, &[]).unwrap(), BodyFraming::UntilClose);
+        assert!(BodyFraming::response("GET", 200, &headers("Content-Length", "x")).is_err());
+        let mut framing = BodyFraming::response("GET", 200, &headers("Transfer-Encoding", "chunked")).unwrap();
+        assert!(framing.consume(b"zz\r\n").is_err());
+    }
+
+    #[test]
+    fn chunked_body_should_decode_split_chunks() {
+        let mut chunked = ChunkedBody::default();
+        let mut out = Vec::new();
+        let data = b"4\r\nWiki\r\n7\r\npedia i\r\nB\r\nn chunks.\r\n\r\n0\r\n\r\n";
+        for part in data.chunks(5) {
+            assert_eq!(chunked.decode(part, &mut out).unwrap(), part.len());
+        }
+
+        assert!(chunked.is_complete());
+        assert_eq!(out, b"Wikipedia in chunks.\r\n");
+    }
+
+    #[test]
+    fn proxy_rewriting_should_replace_urls_and_cookie_scope() {
+        let redirects = vec![("http://10.0.0.5:8080/".to_string(), "https://example.com/".to_string())];
+        assert_eq!(Forwarding::replace_prefix("http://10.0.0.5:8080/login?a=1", &redirects), "https://example.com/login?a=1");
+        assert_eq!(Forwarding::replace_prefix("http://10.0.0.5:80801/", &redirects), "http://10.0.0.5:80801/");
+
+        let domains = vec![("app.internal".to_string(), "example.com".to_string())];
+        let paths = vec![("/app/".to_string(), "/".to_string())];
+        assert_eq!(
+            Forwarding::rewrite_cookie("sid=1;Domain=.APP.internal; path=/app/admin; HttpOnly", &domains, &paths),
+            "sid=1; Domain=example.com; Path=/admin; HttpOnly"
+        );
+        assert_eq!(Forwarding::rewrite_cookie("sid=1; Domain=other.com", &domains, &paths), "sid=1; Domain=other.com");
+    }
+
+    #[tokio::test]
+    async fn request_headers_should_add_forwarding_headers() {
+        let stream = http_stream("GET / HTTP/1.1\r\nHost: example.com:8080\r\nX-Forwarded-For: 203.0.113.7\r\n\
+            X-Forwarded-Proto: https\r\nConnection: keep-alive, X-Secret\r\nX-Secret: 1\r\nTE: trailers\r\n\r\n").await;
+        let mut conf = Conf::new(vec![String::new()]).unwrap();
+        conf.proxy_x_forwarded = true;
+        conf.proxy_forwarded = true;
+        let peer: SocketAddr = "[2001:db8::1]:51234".parse().unwrap();
+
+        let headers = Forwarding::request_headers(&stream, Some(peer), &conf);
+        assert_eq!(find(&headers, "x-forwarded-for"), Some("203.0.113.7, 2001:db8::1"));
+        assert_eq!(find(&headers, "x-forwarded-proto"), Some("http"));
+        assert_eq!(find(&headers, "x-forwarded-host"), Some("example.com:8080"));
+        assert_eq!(find(&headers, "x-forwarded-port"), Some("8080"));
+        assert_eq!(find(&headers, "forwarded"), Some("for=\"[2001:db8::1]\";proto=http;host=\"example.com:8080\""));
+        assert_eq!(find(&headers, "x-secret"), None);
+        assert_eq!(find(&headers, "te"), None);
+        assert_eq!(find(&headers, "connection"), Some("close"));
+
+        conf.trusted_proxies.push(Cidr::parse("2001:db8::/32").unwrap());
+        let headers = Forwarding::request_headers(&stream, Some(peer), &conf);
+        assert_eq!(find(&headers, "x-forwarded-proto"), Some("https"));
+    }
+
+    #[tokio::test]
+    async fn access_rules_should_apply_to_locations_entered_by_rewrites() {
+        let (port, _tx) = start_server("storm_access_server_test", "try_files = $uri /index.html\n\
+            rewrite = ^/go/(.*)$ /admin/$1 last\n\
+            [location /admin]\n\
+            access.deny = all\n").await;
+
+        assert_eq!(status(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await, 200);
+        assert_eq!(status(port, "GET /admin/missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await, 403);
+        assert_eq!(status(port, "GET /go/missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await, 403);
+    }
+
+    #[tokio::test]
+    async fn basic_auth_should_be_kept_after_try_files_fallback() {
+        let htpasswd = std::env::temp_dir().join("storm_auth_server_test.htpasswd");
+        // admin:secret
+        std::fs::write(&htpasswd, "admin:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n").unwrap();
+        let conf = format!("try_files = $uri /index.html\n\
+            [location /admin]\n\
+            auth.basic = \"Admin\"\n\
+            auth.htpasswd = {}\n", htpasswd.to_str().unwrap());
+        let (port, _tx) = start_server("storm_auth_server_test", &conf).await;
+
+        assert_eq!(status(port, "GET /admin/missing HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await, 401);
+        assert_eq!(status(port, "GET /admin/missing HTTP/1.1\r\nHost: localhost\r\n\
+            Authorization: Basic YWRtaW46c2VjcmV0\r\nConnection: close\r\n\r\n").await, 200);
+    }
+
+    #[tokio::test]
+    async fn server_should_close_connections_without_complete_header() {
+        let (port, _tx) = start_server("storm_timeout_server_test", "server.timeout = 1\n").await;
+        assert_eq!(status(port, "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await, 200);
+
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: local").await.unwrap();
+        let mut buff = [0; 16];
+        let read = tokio::time::timeout(Duration::from_secs(3), stream.read(&mut buff)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+    }
+
+    #[tokio::test]
+    async fn rewrite_should_stop_rules_rewriting_to_each_other() {
+        let path = std::env::temp_dir().join("storm_rewrite_cycle_test.conf");
+        std::fs::write(&path, "rewrite = ^/a$ /b last\nrewrite = ^/b$ /a last\n").unwrap();
+        let conf = Conf::new(vec![String::new(), "-f".to_string(), path.to_str().unwrap().to_string()]).unwrap();
+        let mut stream = http_stream("GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+
+        assert!(matches!(Rewrite::apply(&mut stream, &conf, &mut Vec::new()), Err(RewriteError::Cycle(_))));
+        let mut stream = http_stream("GET /c HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(Rewrite::apply(&mut stream, &conf, &mut Vec::new()).is_ok());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::conf::upstream::Upstream;
+use crate::conf::upstream_tls::TlsClient;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+pub enum UpstreamSocket {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl UpstreamSocket {
+    /// Connects to the server within `connect_timeout`. The PROXY protocol header is sent before
+    /// the TLS handshake of `https://` servers.
+    pub async fn connect(upstream: &Upstream,
+                         tls: Option<&TlsClient>,
+                         proxy_header: Option<&[u8]>,
+                         connect_timeout: Duration) -> Result<UpstreamSocket, Box<dyn Error>> {
+        let connect = async {
+            let addr = upstream.addr();
+            let mut stream = TcpStream::connect(addr).await?;
+            if let Some(header) = proxy_header {
+                stream.write_all(header).await?;
+            }
+            if !upstream.server.tls {
+                return Ok(UpstreamSocket::Plain(stream));
+            }
+            let tls = tls.ok_or("TLS is not configured")?;
+            let connector = TlsConnector::from(tls.config.clone());
+            let stream = connector.connect(tls.server_name(&upstream.server, addr), stream).await?;
+            Ok(UpstreamSocket::Tls(Box::new(stream)))
+        };
+        match timeout(connect_timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err("Connection timed out")?
+        }
+    }
+
+    pub async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            UpstreamSocket::Plain(s) => s.read(buf).await,
+            // Many servers close the connection without close_notify after the response
+            UpstreamSocket::Tls(s) => match s.read(buf).await {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(0),
+                result => result
+            },
+        }
+    }
+
+    pub async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            UpstreamSocket::Plain(s) => s.write_all(buf).await,
+            UpstreamSocket::Tls(s) => s.write_all(buf).await,
+        }
+    }
+}
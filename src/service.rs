@@ -1,4 +1,3 @@
-use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::PathBuf;
@@ -27,11 +26,9 @@ pub async fn run_storm_service(dir: PathBuf) -> Result<Vec<(JoinHandle<i32>, Sen
     }
     let logger = Logger::new(Some(logs_dir));
     logger.log_i("Service is running");
-    let conf_groups = get_server_confs(conf_dir, logger.clone()).await?;
-    for (_, confs) in conf_groups {
-        if let Ok(sender) = run_http_server(confs ,logger.clone()).await {
-            senders.push(sender);
-        }
+    let confs = get_server_confs(conf_dir, logger.clone()).await?;
+    if let Ok(sender) = run_http_server(confs ,logger.clone()).await {
+        senders.push(sender);
     }
     Ok(senders)
 }
@@ -54,11 +51,12 @@ async fn run_http_server(confs: Vec<Conf>, logger: Logger) -> Result<(JoinHandle
     Ok((handle, shutdown_tx))
 }
 
-async fn get_server_confs(dir: PathBuf, logger: Logger) -> Result<HashMap<u16, Vec<Conf>>, Box<dyn Error>> {
-    let mut configurations:HashMap<u16, Vec<Conf>> = HashMap::new();
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
+async fn get_server_confs(dir: PathBuf, logger: Logger) -> Result<Vec<Conf>, Box<dyn Error>> {
+    let mut configurations: Vec<Conf> = Vec::new();
+    // Files are loaded by name, regex server names are matched in this order
+    let mut paths = fs::read_dir(dir)?.map(|entry| entry.map(|e| e.path())).collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    for path in paths {
         if path.is_file() && path.extension().is_some_and(|p| p == "conf") {
             let path = &path.to_string_lossy();
             let args: Vec<String> = vec!["", "-f", path].
@@ -67,10 +65,7 @@ async fn get_server_confs(dir: PathBuf, logger: Logger) -> Result<HashMap<u16, V
                 collect();
             match  Conf::new(args) {
                 Ok(conf) => {
-                    configurations
-                        .entry(conf.port)
-                        .or_insert_with(Vec::new)
-                        .push(conf);
+                    configurations.push(conf);
                 }
                 Err(e) => {
                     logger.log_e(